@@ -6,24 +6,49 @@ use crate::*;
 
 use rustcommon_atomics::*;
 use rustcommon_histogram::{AtomicCounter, AtomicHistogram, Counter, Indexing};
-use rustcommon_time::{AtomicInstant, Duration, Instant};
+use rustcommon_time::{Duration, Instant};
 
-// use std::time::{Duration, Instant};
+// Sentinel epoch used to mark a slice which is in the process of being cleared
+// and reused for a newer tick.
+const ROTATING: u64 = u64::MAX;
+
+// Number of times a thread spins while waiting on a rotation or on in-flight
+// writers before yielding the thread.
+const SPINS: usize = 64;
 
 /// AtomicHeatmaps are concurrent datastructures which store counts for
 /// timestamped values over a configured time range with individual histograms
-/// arranged in a ring buffer. Increments occur in the most recent slice in the
-/// buffer, unless they are newer than that slice may hold. When this happens,
-/// old slices are cleared and reused. This configuration results in a fully
-/// pre-allocated datastructure with concurrent read-write access.
+/// arranged in a ring buffer. Each increment is attributed to the slice which
+/// covers its timestamp. When time advances past the newest slice, old slices
+/// are cleared and reused. This configuration results in a fully pre-allocated
+/// datastructure with concurrent read-write access.
+///
+/// Each slice is tagged with the epoch (tick number) it currently holds. Time
+/// advances by having a single writer win a compare-and-swap on the newest
+/// tick, after which it rotates the slices covering the new ticks.
+///
+/// The tick path is not lock-free. A writer whose slice has not yet been
+/// rotated to hold its tick waits for the rotation to finish, so that the
+/// sample is recorded into the window which covers its time. The rotating
+/// writer in turn waits for in-flight increments to the old window to finish
+/// before subtracting it from the summary. Both spin briefly and then yield
+/// the thread while waiting, so a preempted thread delays the others.
 pub struct AtomicHeatmap<Value, Count> {
-    slices: Vec<AtomicHistogram<Value, Count>>,
-    current: AtomicUsize,
-    next_tick: AtomicInstant,
+    slices: Vec<Slice<Value, Count>>,
+    start: Instant,
+    tick: AtomicU64,
     resolution: Duration,
     summary: AtomicHistogram<Value, Count>,
 }
 
+// A single slice of the ring buffer, tagged with the epoch it holds and the
+// number of writers currently incrementing it.
+struct Slice<Value, Count> {
+    epoch: AtomicU64,
+    writers: AtomicUsize,
+    histogram: AtomicHistogram<Value, Count>,
+}
+
 impl<Value, Count> AtomicHeatmap<Value, Count>
 where
     Value: Indexing,
@@ -45,15 +70,27 @@ where
         let mut slices = Vec::new();
         let mut true_span = Duration::new(0, 0);
         while true_span < span {
-            slices.push(AtomicHistogram::new(max, precision));
+            slices.push(Slice {
+                epoch: AtomicU64::new(0),
+                writers: AtomicUsize::new(0),
+                histogram: AtomicHistogram::new(max, precision),
+            });
             true_span += resolution;
         }
         slices.shrink_to_fit();
-        let next_tick = AtomicInstant::new(Instant::now() + resolution);
+
+        // Ticks are offset by the number of slices so that the windows which
+        // precede the creation of the heatmap have non-negative epochs.
+        let len = slices.len() as u64;
+        for (index, slice) in slices.iter().enumerate() {
+            let epoch = if index == 0 { len } else { index as u64 };
+            slice.epoch.store(epoch, Ordering::Relaxed);
+        }
+
         Self {
             slices,
-            current: AtomicUsize::new(0),
-            next_tick,
+            start: Instant::now(),
+            tick: AtomicU64::new(len),
             resolution,
             summary: AtomicHistogram::new(max, precision),
         }
@@ -70,12 +107,41 @@ where
         self.summary.buckets()
     }
 
-    /// Increment a time-value pair by a specified count
+    /// Returns the duration of time covered by each window of the `AtomicHeatmap`
+    pub fn resolution(&self) -> Duration {
+        self.resolution
+    }
+
+    /// Increment a time-value pair by a specified count. The count is recorded
+    /// into the window which covers the provided time, waiting for that window
+    /// to be rotated in if another thread is still rotating it. Times which
+    /// are older than the span retained by the heatmap are discarded.
     pub fn increment(&self, time: Instant, value: Value, count: <Count as Atomic>::Primitive) {
-        self.tick(time);
-        if let Some(slice) = self.slices.get(self.current.load(Ordering::Relaxed)) {
-            slice.increment(value, count);
-            self.summary.increment(value, count);
+        let tick = self.tick_at(time);
+        self.advance(tick);
+        let len = self.slices.len() as u64;
+        if tick + len <= self.tick.load(Ordering::SeqCst) {
+            // the window for this time has already aged out
+            return;
+        }
+        let slice = &self.slices[(tick % len) as usize];
+        let mut spins = 0;
+        loop {
+            slice.writers.fetch_add(1, Ordering::SeqCst);
+            let epoch = slice.epoch.load(Ordering::SeqCst);
+            if epoch == tick {
+                slice.histogram.increment(value, count);
+                self.summary.increment(value, count);
+            }
+            slice.writers.fetch_sub(1, Ordering::SeqCst);
+            if epoch == tick || (epoch != ROTATING && epoch > tick) {
+                // recorded, or the slice has been reused for a newer window
+                // and this time has aged out
+                return;
+            }
+            // the slice is still being rotated to hold this tick. The writer
+            // count is released while waiting, so the rotation can finish.
+            backoff(&mut spins);
         }
     }
 
@@ -93,34 +159,95 @@ where
     /// threads are not writing into the heatmap while this function is
     /// in-progress.
     pub fn percentile(&self, percentile: f64) -> Result<Value, HeatmapError> {
-        self.tick(Instant::now());
+        self.advance(self.tick_at(Instant::now()));
         self.summary
             .percentile(percentile)
             .map_err(|e| HeatmapError::from(e))
     }
 
-    // Internal function which handles reuse of older windows to store newer
-    /// values.
-    fn tick(&self, time: Instant) {
+    /// Internal function which returns the epoch of the window covering the
+    /// provided time. Times before the creation of the heatmap are attributed
+    /// to the first window.
+    fn tick_at(&self, time: Instant) -> u64 {
+        let elapsed = time.saturating_duration_since(self.start).as_nanos();
+        self.slices.len() as u64 + (elapsed / self.resolution.as_nanos()) as u64
+    }
+
+    /// Internal function which advances the newest tick, reusing older windows
+    /// to store newer values. Only the writer which successfully advances the
+    /// tick rotates the slices for the ticks it skipped over.
+    fn advance(&self, tick: u64) {
+        let mut newest = self.tick.load(Ordering::SeqCst);
+        while newest < tick {
+            match self
+                .tick
+                .compare_exchange(newest, tick, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => {
+                    let len = self.slices.len() as u64;
+                    let first = core::cmp::max(newest + 1, tick + 1 - len);
+                    for epoch in first..=tick {
+                        self.rotate(epoch);
+                    }
+                    return;
+                }
+                Err(current) => {
+                    newest = current;
+                }
+            }
+        }
+    }
+
+    /// Internal function which clears the slice for the provided epoch and
+    /// subtracts its contents from the summary. Slices which already hold a
+    /// newer epoch are left untouched.
+    fn rotate(&self, epoch: u64) {
+        let slice = &self.slices[(epoch % self.slices.len() as u64) as usize];
+        let mut spins = 0;
         loop {
-            let next_tick = self.next_tick.load(Ordering::Relaxed);
-            if time < next_tick {
+            let current = slice.epoch.load(Ordering::SeqCst);
+            if current == ROTATING {
+                backoff(&mut spins);
+                continue;
+            }
+            if current >= epoch {
                 return;
-            } else {
-                self.next_tick.fetch_add(self.resolution, Ordering::Relaxed);
-                self.current.fetch_add(1, Ordering::Relaxed);
-                if self.current.load(Ordering::Relaxed) >= self.slices.len() {
-                    self.current.store(0, Ordering::Relaxed);
-                }
-                let current = self.current.load(Ordering::Relaxed);
-                if let Some(slice) = self.slices.get(current) {
-                    self.summary.sub_assign(slice);
-                    slice.clear();
+            }
+            if slice
+                .epoch
+                .compare_exchange(current, ROTATING, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                // wait for in-flight writers to finish with the old window
+                while slice.writers.load(Ordering::SeqCst) != 0 {
+                    backoff(&mut spins);
                 }
+                self.summary.sub_assign(&slice.histogram);
+                slice.histogram.clear();
+                slice.epoch.store(epoch, Ordering::SeqCst);
+                return;
             }
         }
     }
 
+    /// Internal function which returns the histogram of the slice for the
+    /// provided epoch. If the slice is being rotated, the histogram may be
+    /// partially cleared.
+    fn histogram(&self, epoch: u64) -> &AtomicHistogram<Value, Count> {
+        &self.slices[(epoch % self.slices.len() as u64) as usize].histogram
+    }
+
+    /// Internal function which returns the start of the window for an epoch.
+    fn window_start(&self, epoch: u64) -> Instant {
+        let len = self.slices.len() as u64;
+        let resolution = self.resolution.as_nanos() as u64;
+        if epoch >= len {
+            self.start + Duration::from_nanos(resolution * (epoch - len))
+        } else {
+            self.start - Duration::from_nanos(resolution * (len - epoch))
+        }
+    }
+
     /// Performs a `Relaxed` load of the current `AtomicHeatmap` allocating and
     /// returning a non-atomic `Heatmap`.
     ///
@@ -133,21 +260,101 @@ where
         Value: Copy + std::ops::Sub<Output = Value>,
         <Count as Atomic>::Primitive: Counter,
     {
+        let newest = self.tick.load(Ordering::SeqCst);
+        let len = self.slices.len() as u64;
         let mut result = Heatmap {
             slices: Vec::with_capacity(self.slices.len()),
-            current: self.current.load(Ordering::Relaxed),
-            next_tick: self.next_tick.load(Ordering::Relaxed),
+            current: (newest % len) as usize,
+            next_tick: self.window_start(newest) + self.resolution,
             resolution: self.resolution,
             summary: self.summary.load(),
         };
-        for slice in &self.slices {
-            result.slices.push(slice.load());
+        for index in 0..len {
+            // the epoch held by this index within the retained span
+            let epoch = newest - ((newest + len - index) % len);
+            result.slices.push(self.histogram(epoch).load());
         }
         result.slices.shrink_to_fit();
         result
     }
 }
 
+// Spin briefly, then yield the thread, while waiting on another thread.
+fn backoff(spins: &mut usize) {
+    if *spins < SPINS {
+        *spins += 1;
+        core::hint::spin_loop();
+    } else {
+        std::thread::yield_now();
+    }
+}
+
+/// An iterator which yields borrowed `AtomicWindow`s from oldest to newest. The
+/// newest tick is captured when the iterator is created, while bucket counts
+/// are loaded lazily as each window's histogram is iterated.
+pub struct Iter<'a, Value, Count> {
+    inner: &'a AtomicHeatmap<Value, Count>,
+    epoch: u64,
+    newest: u64,
+}
+
+impl<'a, Value, Count> Iter<'a, Value, Count>
+where
+    Value: Indexing,
+    Count: AtomicCounter + Default,
+    u64: From<Value> + From<<Count as Atomic>::Primitive>,
+    <Count as Atomic>::Primitive: Copy,
+{
+    fn new(inner: &'a AtomicHeatmap<Value, Count>) -> Iter<'a, Value, Count> {
+        let newest = inner.tick.load(Ordering::SeqCst);
+        Iter {
+            inner,
+            epoch: newest + 1 - inner.slices.len() as u64,
+            newest,
+        }
+    }
+}
+
+impl<'a, Value, Count> Iterator for Iter<'a, Value, Count>
+where
+    Value: Indexing,
+    Count: AtomicCounter + Default,
+    u64: From<Value> + From<<Count as Atomic>::Primitive>,
+    <Count as Atomic>::Primitive: Copy,
+{
+    type Item = AtomicWindow<'a, Value, Count>;
+
+    fn next(&mut self) -> Option<AtomicWindow<'a, Value, Count>> {
+        if self.epoch > self.newest {
+            None
+        } else {
+            let start = self.inner.window_start(self.epoch);
+            let window = AtomicWindow {
+                start,
+                stop: start + self.inner.resolution,
+                histogram: self.inner.histogram(self.epoch),
+            };
+            self.epoch += 1;
+            Some(window)
+        }
+    }
+}
+
+impl<'a, Value, Count> IntoIterator for &'a AtomicHeatmap<Value, Count>
+where
+    Value: Indexing,
+    Count: AtomicCounter + Default,
+    u64: From<Value> + From<<Count as Atomic>::Primitive>,
+    <Count as Atomic>::Primitive: Copy,
+{
+    type Item = AtomicWindow<'a, Value, Count>;
+    type IntoIter = Iter<'a, Value, Count>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Iter::new(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,4 +385,125 @@ mod tests {
         std::thread::sleep(std::time::Duration::from_millis(2000));
         assert_eq!(heatmap.percentile(0.0), Err(HeatmapError::Empty));
     }
+
+    #[test]
+    fn windows() {
+        let heatmap = AtomicHeatmap::<u64, AtomicU64>::new(
+            1_000_000,
+            2,
+            Duration::new(60, 0),
+            Duration::new(10, 0),
+        );
+        heatmap.increment(Instant::now(), 1, 1);
+        heatmap.increment(Instant::now(), 100, 2);
+
+        let windows: Vec<_> = heatmap.into_iter().collect();
+        assert_eq!(windows.len(), heatmap.windows());
+
+        // windows are ordered oldest to newest and do not overlap
+        for pair in windows.windows(2) {
+            assert_eq!(pair[0].stop(), pair[1].start());
+        }
+
+        // the counts are only present in the newest window
+        let total = |window: &AtomicWindow<u64, AtomicU64>| -> u64 {
+            window.histogram().into_iter().map(|b| b.count()).sum()
+        };
+        let newest = windows.last().unwrap();
+        assert_eq!(total(newest), 3);
+        assert_eq!(windows.iter().map(total).sum::<u64>(), 3);
+
+        // writes after the view is created are visible through the view
+        heatmap.increment(Instant::now(), 1, 1);
+        assert_eq!(total(newest), 4);
+    }
+
+    #[test]
+    fn attribution() {
+        let heatmap = AtomicHeatmap::<u64, AtomicU64>::new(
+            1_000,
+            2,
+            Duration::new(60, 0),
+            Duration::new(10, 0),
+        );
+        let start = heatmap.start;
+
+        // advance the heatmap by a few windows and then record into an older
+        // window which is still retained
+        heatmap.increment(start + Duration::new(35, 0), 1, 1);
+        heatmap.increment(start + Duration::new(15, 0), 1, 2);
+
+        let windows: Vec<_> = heatmap.into_iter().collect();
+        let counts: Vec<u64> = windows
+            .iter()
+            .map(|w| w.histogram().into_iter().map(|b| b.count()).sum())
+            .collect();
+        assert_eq!(counts, vec![0, 0, 0, 2, 0, 1]);
+
+        // times beyond the retained span are discarded
+        heatmap.increment(start + Duration::new(125, 0), 1, 1);
+        heatmap.increment(start + Duration::new(15, 0), 1, 1);
+        let counts: Vec<u64> = heatmap
+            .into_iter()
+            .map(|w| w.histogram().into_iter().map(|b| b.count()).sum())
+            .collect();
+        assert_eq!(counts, vec![0, 0, 0, 0, 0, 1]);
+
+        // the windows of a loaded heatmap match the atomic windows
+        let loaded = heatmap.load();
+        for (atomic, window) in heatmap.into_iter().zip(&loaded) {
+            assert_eq!(atomic.start(), window.start());
+            assert_eq!(atomic.stop(), window.stop());
+        }
+    }
+
+    #[test]
+    fn concurrent_ticks() {
+        let threads = 8;
+        let increments = 100_000;
+
+        // short resolution so writers cross many tick boundaries, but a span
+        // long enough that no samples age out during the test
+        let heatmap = std::sync::Arc::new(AtomicHeatmap::<u64, AtomicU64>::new(
+            1_000,
+            2,
+            Duration::new(30, 0),
+            Duration::from_millis(1),
+        ));
+
+        // each sample records its tick, modulo a range of values which are
+        // stored exactly, so that misattributed samples can be detected
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let heatmap = heatmap.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..increments {
+                        let time = Instant::now();
+                        heatmap.increment(time, heatmap.tick_at(time) % 100, 1);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // every increment is recorded, and the summary matches the windows
+        let expected = (threads * increments) as u64;
+        let newest = heatmap.tick.load(Ordering::SeqCst);
+        let len = heatmap.windows() as u64;
+        let mut windows = 0;
+        for epoch in (newest + 1 - len)..=newest {
+            for bucket in heatmap.histogram(epoch) {
+                if bucket.count() > 0 {
+                    assert_eq!(bucket.value(), epoch % 100);
+                    windows += bucket.count();
+                }
+            }
+        }
+        assert_eq!(windows, expected);
+
+        let summary: u64 = heatmap.summary.into_iter().map(|b| b.count()).sum();
+        assert_eq!(summary, expected);
+    }
 }
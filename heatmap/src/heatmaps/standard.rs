@@ -65,6 +65,11 @@ where
         self.summary.buckets()
     }
 
+    /// Returns the duration of time covered by each window of the `Heatmap`
+    pub fn resolution(&self) -> Duration {
+        self.resolution
+    }
+
     /// Increment a time-value pair by a specified count
     pub fn increment(&mut self, time: Instant, value: Value, count: Count) {
         self.tick(time);
@@ -105,9 +110,9 @@ where
         }
     }
 
-    /// Internal function to return a `Window` from the `Heatmap`.
-    fn get_slice(&self, index: usize) -> Option<Window<Value, Count>> {
-        if let Some(histogram) = self.slices.get(index).map(|v| (*v).clone()) {
+    /// Internal function to return a borrowed `Window` from the `Heatmap`.
+    fn get_slice(&self, index: usize) -> Option<Window<'_, Value, Count>> {
+        if let Some(histogram) = self.slices.get(index) {
             let shift = if index > self.current {
                 self.resolution
                     .mul_f64((self.slices.len() + self.current - index) as f64)
@@ -162,9 +167,9 @@ where
     Count: Counter,
     u64: From<Value> + From<Count>,
 {
-    type Item = Window<Value, Count>;
+    type Item = Window<'a, Value, Count>;
 
-    fn next(&mut self) -> Option<Window<Value, Count>> {
+    fn next(&mut self) -> Option<Window<'a, Value, Count>> {
         if self.visited >= self.inner.slices.len() {
             None
         } else {
@@ -185,7 +190,7 @@ where
     Count: Counter,
     u64: From<Value> + From<Count>,
 {
-    type Item = Window<Value, Count>;
+    type Item = Window<'a, Value, Count>;
     type IntoIter = Iter<'a, Value, Count>;
 
     fn into_iter(self) -> Self::IntoIter {
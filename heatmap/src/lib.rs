@@ -8,7 +8,7 @@ mod window;
 
 pub use error::HeatmapError;
 pub use heatmaps::{AtomicHeatmap, Heatmap};
-pub use window::Window;
+pub use window::{AtomicWindow, Window};
 
 pub use rustcommon_atomics::{Atomic, AtomicU16, AtomicU32, AtomicU64, AtomicU8};
 pub use rustcommon_histogram::{AtomicCounter, Counter, Indexing};
@@ -2,25 +2,56 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use rustcommon_histogram::Histogram;
+use rustcommon_histogram::{AtomicHistogram, Histogram};
 use rustcommon_time::Instant;
 
-pub struct Window<Value, Count> {
+/// A borrowed view of a single time window within a `Heatmap`.
+pub struct Window<'a, Value, Count> {
     pub(crate) start: Instant,
     pub(crate) stop: Instant,
-    pub(crate) histogram: Histogram<Value, Count>,
+    pub(crate) histogram: &'a Histogram<Value, Count>,
 }
 
-impl<Value, Count> Window<Value, Count> {
+impl<'a, Value, Count> Window<'a, Value, Count> {
+    /// Returns the start of the time range covered by this `Window`
     pub fn start(&self) -> Instant {
         self.start
     }
 
+    /// Returns the end of the time range covered by this `Window`
     pub fn stop(&self) -> Instant {
         self.stop
     }
 
-    pub fn histogram(&self) -> &Histogram<Value, Count> {
-        &self.histogram
+    /// Returns the `Histogram` holding the samples recorded in this `Window`
+    pub fn histogram(&self) -> &'a Histogram<Value, Count> {
+        self.histogram
+    }
+}
+
+/// A borrowed view of a single time window within an `AtomicHeatmap`. The
+/// bucket counts are read lazily while iterating over the histogram, so no
+/// copy of the window is allocated.
+pub struct AtomicWindow<'a, Value, Count> {
+    pub(crate) start: Instant,
+    pub(crate) stop: Instant,
+    pub(crate) histogram: &'a AtomicHistogram<Value, Count>,
+}
+
+impl<'a, Value, Count> AtomicWindow<'a, Value, Count> {
+    /// Returns the start of the time range covered by this `AtomicWindow`
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    /// Returns the end of the time range covered by this `AtomicWindow`
+    pub fn stop(&self) -> Instant {
+        self.stop
+    }
+
+    /// Returns the `AtomicHistogram` holding the samples recorded in this
+    /// `AtomicWindow`
+    pub fn histogram(&self) -> &'a AtomicHistogram<Value, Count> {
+        self.histogram
     }
 }
@@ -178,11 +178,9 @@ where
                     other.buckets[i].load(Ordering::Relaxed),
                     Ordering::Relaxed,
                 );
-                self.too_high.fetch_saturating_sub(
-                    other.too_high.load(Ordering::Relaxed),
-                    Ordering::Relaxed,
-                );
             }
+            self.too_high
+                .fetch_saturating_sub(other.too_high.load(Ordering::Relaxed), Ordering::Relaxed);
         } else {
             // slow path if we need to calculate appropriate index for each bucket
             for bucket in other {
@@ -213,11 +211,9 @@ where
                     other.buckets[i].load(Ordering::Relaxed),
                     Ordering::Relaxed,
                 );
-                self.too_high.fetch_saturating_add(
-                    other.too_high.load(Ordering::Relaxed),
-                    Ordering::Relaxed,
-                );
             }
+            self.too_high
+                .fetch_saturating_add(other.too_high.load(Ordering::Relaxed), Ordering::Relaxed);
         } else {
             // slow path if we need to calculate appropriate index for each bucket
             for bucket in other {
@@ -0,0 +1,146 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Per-target level filtering, configured with `RUST_LOG` style directives.
+
+use log::LevelFilter;
+
+use core::fmt;
+use core::str::FromStr;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// A set of directives which select the level for each target. Directives
+/// are separated by commas, and are either a level which applies to every
+/// target, or `target=level` which applies to the target and the modules
+/// within it. For example, `info,my_crate::net=debug,hyper=warn`. When
+/// several directives match a target, the one with the longest target wins.
+pub struct Directives {
+    default: Option<LevelFilter>,
+    // sorted from the longest target to the shortest
+    targets: Vec<(String, LevelFilter)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The error returned when directives cannot be parsed
+pub struct ParseDirectivesError {
+    directive: String,
+}
+
+impl fmt::Display for ParseDirectivesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid log directive: {}", self.directive)
+    }
+}
+
+impl std::error::Error for ParseDirectivesError {}
+
+impl Directives {
+    /// The level for the target, or `None` if no directive matches it
+    pub fn level(&self, target: &str) -> Option<LevelFilter> {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| matches(prefix, target))
+            .map(|(_, level)| *level)
+            .or(self.default)
+    }
+
+    // remove the directive without a target, if any
+    pub(crate) fn remove_default(&mut self) {
+        self.default = None;
+    }
+
+    /// The most verbose level which any target may be logged at, given the
+    /// level used for targets which no directive matches
+    pub fn max_level(&self, default: LevelFilter) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default.unwrap_or(default), Ord::max)
+    }
+}
+
+impl FromStr for Directives {
+    type Err = ParseDirectivesError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut directives = Directives::default();
+        for directive in text.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()) {
+            let invalid = || ParseDirectivesError {
+                directive: directive.to_string(),
+            };
+            match directive.find('=') {
+                Some(i) => {
+                    let target = directive[..i].trim();
+                    let level = directive[i + 1..].trim().parse().map_err(|_| invalid())?;
+                    if target.is_empty() {
+                        return Err(invalid());
+                    }
+                    // a later directive for the same target replaces it
+                    directives.targets.retain(|(t, _)| t != target);
+                    directives.targets.push((target.to_string(), level));
+                }
+                None => match directive.parse() {
+                    Ok(level) => directives.default = Some(level),
+                    // a bare target enables every level for it
+                    Err(_) => {
+                        directives.targets.retain(|(t, _)| t != directive);
+                        directives
+                            .targets
+                            .push((directive.to_string(), LevelFilter::Trace));
+                    }
+                },
+            }
+        }
+        directives
+            .targets
+            .sort_by_key(|(target, _)| core::cmp::Reverse(target.len()));
+        Ok(directives)
+    }
+}
+
+// whether the target is the prefix or a module within it
+pub(crate) fn matches(prefix: &str, target: &str) -> bool {
+    target.starts_with(prefix)
+        && (target.len() == prefix.len() || target[prefix.len()..].starts_with("::"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_prefix() {
+        let directives: Directives = "info,my_crate::net=debug,my_crate=warn,hyper=off"
+            .parse()
+            .unwrap();
+        assert_eq!(directives.level("other"), Some(LevelFilter::Info));
+        assert_eq!(directives.level("my_crate"), Some(LevelFilter::Warn));
+        assert_eq!(directives.level("my_crate::net"), Some(LevelFilter::Debug));
+        assert_eq!(
+            directives.level("my_crate::net::tcp"),
+            Some(LevelFilter::Debug)
+        );
+        // prefixes only match whole path segments
+        assert_eq!(
+            directives.level("my_crate::network"),
+            Some(LevelFilter::Warn)
+        );
+        assert_eq!(directives.level("hyper"), Some(LevelFilter::Off));
+        assert_eq!(directives.max_level(LevelFilter::Trace), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn parse() {
+        let directives: Directives = "tokio".parse().unwrap();
+        assert_eq!(directives.level("tokio::net"), Some(LevelFilter::Trace));
+        assert_eq!(directives.level("other"), None);
+        assert_eq!(
+            Directives::default().max_level(LevelFilter::Warn),
+            LevelFilter::Warn
+        );
+
+        assert!("app=loud".parse::<Directives>().is_err());
+        assert!("=debug".parse::<Directives>().is_err());
+    }
+}
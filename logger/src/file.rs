@@ -0,0 +1,304 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A log file which is rotated by size and/or time, keeping a number of
+//! optionally compressed backups, and which may be reopened on `SIGHUP` for
+//! compatibility with external tools such as logrotate.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// incremented each time the process receives `SIGHUP`
+static HANGUPS: AtomicUsize = AtomicUsize::new(0);
+
+/// A log file which may be rotated. Writes which would take the file past its
+/// maximum size, or which happen after the end of the current rotation
+/// interval, cause the file to be rotated first. The current file is renamed
+/// to `<path>.1`, with existing backups shifted to the next number and the
+/// oldest removed. Rotation happens on the thread which writes the log line,
+/// which is the background thread for an asynchronous logger.
+pub struct FileSink {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    interval: Option<Duration>,
+    next_rotation: Option<SystemTime>,
+    backups: usize,
+    compress: bool,
+    reopen: bool,
+    hangups: usize,
+}
+
+impl FileSink {
+    /// Open the file at the path for appending, creating it if necessary. By
+    /// default, the file is never rotated.
+    pub fn new<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size: None,
+            interval: None,
+            next_rotation: None,
+            backups: 5,
+            compress: false,
+            reopen: false,
+            hangups: 0,
+        })
+    }
+
+    /// Rotate the file before it grows beyond the provided number of bytes
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotate the file each interval, aligned to whole multiples of the
+    /// interval since the UNIX epoch. For example, an interval of one day
+    /// rotates the file at midnight UTC.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self.next_rotation = next_rotation(SystemTime::now(), interval);
+        self
+    }
+
+    /// Sets the number of rotated files which are kept. Defaults to 5. With
+    /// no backups, the file is truncated when it is rotated.
+    pub fn backups(mut self, count: usize) -> Self {
+        self.backups = count;
+        self
+    }
+
+    /// Compress rotated files with gzip, adding a `.gz` extension
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Reopen the file at its path after the process receives `SIGHUP`, so
+    /// that an external tool may move the file aside. This installs a
+    /// handler for `SIGHUP` which replaces any existing handler.
+    pub fn reopen_on_sighup(mut self) -> Self {
+        #[cfg(unix)]
+        {
+            install_handler();
+            self.reopen = true;
+            self.hangups = HANGUPS.load(Ordering::Relaxed);
+        }
+        self
+    }
+
+    /// Rotate the file now
+    pub fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+
+        // shift the backups, discarding the oldest
+        if self.backups > 0 {
+            let _ = std::fs::remove_file(self.backup(self.backups));
+            for n in (1..self.backups).rev() {
+                let from = self.backup(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.backup(n + 1))?;
+                }
+            }
+            if self.compress {
+                let mut encoder =
+                    GzEncoder::new(File::create(self.backup(1))?, Compression::default());
+                std::io::copy(&mut File::open(&self.path)?, &mut encoder)?;
+                encoder.finish()?;
+                std::fs::remove_file(&self.path)?;
+            } else {
+                std::fs::rename(&self.path, self.backup(1))?;
+            }
+        } else {
+            std::fs::remove_file(&self.path)?;
+        }
+
+        self.reopen()
+    }
+
+    /// Reopen the file at its path, creating it if it has been moved
+    pub fn reopen(&mut self) -> std::io::Result<()> {
+        self.file = open(&self.path)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    // the path of the numbered backup
+    fn backup(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        if self.compress {
+            name.push(".gz");
+        }
+        PathBuf::from(name)
+    }
+
+    // rotate or reopen the file before writing, if required
+    fn prepare(&mut self, len: usize) -> std::io::Result<()> {
+        if self.reopen {
+            let hangups = HANGUPS.load(Ordering::Relaxed);
+            if hangups != self.hangups {
+                self.hangups = hangups;
+                self.reopen()?;
+            }
+        }
+
+        let mut rotate = false;
+        if let Some(max_size) = self.max_size {
+            rotate |= self.size > 0 && self.size + len as u64 > max_size;
+        }
+        if let (Some(interval), Some(next)) = (self.interval, self.next_rotation) {
+            let now = SystemTime::now();
+            if now >= next {
+                self.next_rotation = next_rotation(now, interval);
+                rotate |= self.size > 0;
+            }
+        }
+        if rotate {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for FileSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.prepare(buf.len())?;
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// the next whole multiple of the interval since the UNIX epoch
+fn next_rotation(now: SystemTime, interval: Duration) -> Option<SystemTime> {
+    let interval = interval.as_nanos();
+    if interval == 0 {
+        return None;
+    }
+    let since_epoch = now.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+    let next = (since_epoch / interval + 1) * interval;
+    Some(UNIX_EPOCH + Duration::new((next / 1_000_000_000) as u64, (next % 1_000_000_000) as u32))
+}
+
+#[cfg(unix)]
+fn install_handler() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+
+    extern "C" fn handle_hangup(_: libc::c_int) {
+        HANGUPS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    INSTALL.call_once(|| {
+        // safety: the handler only performs an atomic increment, which is
+        // async-signal-safe
+        unsafe {
+            libc::signal(
+                libc::SIGHUP,
+                handle_hangup as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    // a fresh directory for the test
+    fn directory(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("rustcommon-logger-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let directory = directory("size");
+        let path = directory.join("test.log");
+        let mut sink = FileSink::new(&path).unwrap().max_size(10).backups(2);
+        for line in &["first\n", "second\n", "third\n", "fourth\n"] {
+            sink.write_all(line.as_bytes()).unwrap();
+        }
+        sink.flush().unwrap();
+
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!(read(&directory.join("test.log.1")), "third\n");
+        assert_eq!(read(&directory.join("test.log.2")), "second\n");
+        assert!(!directory.join("test.log.3").exists());
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn compress() {
+        let directory = directory("compress");
+        let path = directory.join("test.log");
+        let mut sink = FileSink::new(&path).unwrap().compress(true);
+        sink.write_all(b"compressed\n").unwrap();
+        sink.rotate().unwrap();
+        sink.write_all(b"current\n").unwrap();
+
+        let mut decoded = String::new();
+        GzDecoder::new(File::open(directory.join("test.log.1.gz")).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "compressed\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "current\n");
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hangup() {
+        let directory = directory("hangup");
+        let path = directory.join("test.log");
+        let mut sink = FileSink::new(&path).unwrap().reopen_on_sighup();
+        sink.write_all(b"before\n").unwrap();
+
+        // move the file aside, as logrotate would, before signalling
+        std::fs::rename(&path, directory.join("moved.log")).unwrap();
+        unsafe {
+            libc::raise(libc::SIGHUP);
+        }
+        sink.write_all(b"after\n").unwrap();
+
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&directory.join("moved.log")), "before\n");
+        assert_eq!(read(&path), "after\n");
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn interval() {
+        let now = UNIX_EPOCH + Duration::from_secs(90);
+        assert_eq!(
+            next_rotation(now, Duration::from_secs(60)),
+            Some(UNIX_EPOCH + Duration::from_secs(120))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Formatting of log records into lines of text.
+
+use log::kv::{Error, Key, Value, VisitSource};
+use log::Record;
+
+use core::fmt::Write;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Used to select how log records are written
+pub enum Format {
+    /// A single line of text, as `timestamp level [target] message`, followed
+    /// by any key-value pairs as `key=value`
+    Text,
+    /// A JSON object on a single line, with the timestamp, level, target,
+    /// module path, file, line, and message as fields, followed by any
+    /// key-value pairs as additional fields
+    Json,
+}
+
+impl Format {
+    /// Format the record as a line, including the trailing newline. The
+    /// target is provided separately, as it may be replaced by a label.
+    pub(crate) fn line(self, timestamp: &str, target: &str, record: &Record) -> String {
+        let mut line = String::new();
+        match self {
+            Format::Text => {
+                let _ = write!(
+                    line,
+                    "{} {:<5} [{}] {}",
+                    timestamp,
+                    record.level(),
+                    target,
+                    record.args()
+                );
+                let _ = record.key_values().visit(&mut TextFields(&mut line));
+            }
+            Format::Json => {
+                line.push('{');
+                field(&mut line, "timestamp", timestamp);
+                line.push(',');
+                field(&mut line, "level", record.level().as_str());
+                line.push(',');
+                field(&mut line, "target", target);
+                if let Some(module_path) = record.module_path() {
+                    line.push(',');
+                    field(&mut line, "module_path", module_path);
+                }
+                if let Some(file) = record.file() {
+                    line.push(',');
+                    field(&mut line, "file", file);
+                }
+                if let Some(number) = record.line() {
+                    let _ = write!(line, ",\"line\":{}", number);
+                }
+                line.push(',');
+                field(&mut line, "message", &record.args().to_string());
+                let _ = record.key_values().visit(&mut JsonFields(&mut line));
+                line.push('}');
+            }
+        }
+        line.push('\n');
+        line
+    }
+}
+
+// appends each key-value pair as ` key=value`
+struct TextFields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for TextFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let _ = write!(self.0, " {}={}", key, value);
+        Ok(())
+    }
+}
+
+// appends each key-value pair as a JSON field, keeping numbers and booleans
+// as JSON values and writing anything else as a string
+struct JsonFields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        self.0.push(',');
+        string(self.0, key.as_str());
+        self.0.push(':');
+        if let Some(value) = value.to_u64() {
+            let _ = write!(self.0, "{}", value);
+        } else if let Some(value) = value.to_i64() {
+            let _ = write!(self.0, "{}", value);
+        } else if let Some(value) = value.to_f64().filter(|v| v.is_finite()) {
+            let _ = write!(self.0, "{}", value);
+        } else if let Some(value) = value.to_bool() {
+            let _ = write!(self.0, "{}", value);
+        } else {
+            string(self.0, &value.to_string());
+        }
+        Ok(())
+    }
+}
+
+// append a field with a string value
+fn field(output: &mut String, key: &str, value: &str) {
+    string(output, key);
+    output.push(':');
+    string(output, value);
+}
+
+// append a JSON string
+fn string(output: &mut String, text: &str) {
+    output.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            _ => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn formats() {
+        let pairs: &[(&str, Value)] = &[
+            ("id", Value::from(42_u64)),
+            ("peer", Value::from("a \"quoted\" name")),
+        ];
+        let record = Record::builder()
+            .args(format_args!("hello\tworld"))
+            .level(Level::Warn)
+            .target("app::net")
+            .module_path_static(Some("app::net"))
+            .file_static(Some("src/net.rs"))
+            .line(Some(7))
+            .key_values(&pairs)
+            .build();
+
+        assert_eq!(
+            Format::Text.line("2021-01-01T00:00:00.000Z", "app", &record),
+            "2021-01-01T00:00:00.000Z WARN  [app] hello\tworld id=42 peer=a \"quoted\" name\n"
+        );
+        assert_eq!(
+            Format::Json.line("2021-01-01T00:00:00.000Z", "app::net", &record),
+            "{\"timestamp\":\"2021-01-01T00:00:00.000Z\",\"level\":\"WARN\",\
+             \"target\":\"app::net\",\"module_path\":\"app::net\",\"file\":\"src/net.rs\",\
+             \"line\":7,\"message\":\"hello\\tworld\",\"id\":42,\
+             \"peer\":\"a \\\"quoted\\\" name\"}\n"
+        );
+    }
+}
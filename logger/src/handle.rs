@@ -0,0 +1,169 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! The levels in effect for a logger, which may be changed after it has been
+//! initialized through a handle.
+
+use crate::Directives;
+
+use crossbeam_epoch::{self as epoch, Atomic, Owned};
+use log::{Level, LevelFilter, Metadata};
+
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+// the level for targets which are not matched by a directive, and the
+// directives
+#[derive(Clone)]
+struct State {
+    level: LevelFilter,
+    directives: Directives,
+}
+
+impl State {
+    fn max_level(&self) -> LevelFilter {
+        self.directives.max_level(self.level)
+    }
+}
+
+// the levels in effect, which are read without locking by every record and
+// replaced as a whole when they change, with the previous state reclaimed
+// once no reader can still be using it
+pub(crate) struct Filter {
+    state: Atomic<State>,
+    // serializes changes, so that concurrent changes cannot leave a stale
+    // maximum level behind
+    lock: Mutex<()>,
+}
+
+impl Filter {
+    pub fn new(level: Level, directives: Directives) -> Self {
+        Self {
+            state: Atomic::new(State {
+                level: level.to_level_filter(),
+                directives,
+            }),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        self.read(|state| {
+            let level = state
+                .directives
+                .level(metadata.target())
+                .unwrap_or(state.level);
+            metadata.level() <= level
+        })
+    }
+
+    pub fn max_level(&self) -> LevelFilter {
+        self.read(State::max_level)
+    }
+
+    fn read<T, F: FnOnce(&State) -> T>(&self, read: F) -> T {
+        let guard = epoch::pin();
+        let state = self.state.load(Ordering::Acquire, &guard);
+        // the state is only ever replaced, never null, and the previous state
+        // is not reclaimed while this thread is pinned
+        read(unsafe { state.deref() })
+    }
+
+    // replace the state with a changed copy and update the global maximum
+    // level
+    fn update<F: FnOnce(&mut State)>(&self, change: F) {
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut state = self.read(State::clone);
+        change(&mut state);
+        let max_level = state.max_level();
+        let guard = epoch::pin();
+        let previous = self.state.swap(Owned::new(state), Ordering::AcqRel, &guard);
+        // no new readers can load the previous state once it is swapped out
+        unsafe { guard.defer_destroy(previous) };
+        log::set_max_level(max_level);
+    }
+}
+
+impl Drop for Filter {
+    fn drop(&mut self) {
+        // there are no other references to the filter, so the state may be
+        // reclaimed immediately
+        unsafe {
+            drop(
+                self.state
+                    .load(Ordering::Relaxed, epoch::unprotected())
+                    .into_owned(),
+            );
+        }
+    }
+}
+
+#[derive(Clone)]
+/// A handle to the initialized logger, which is used to change its levels at
+/// runtime, for example from an admin endpoint or a signal handler. Changes
+/// apply to all threads, and also update `log::max_level()` so that records
+/// which are disabled by every directive are skipped cheaply.
+pub struct LogHandle {
+    filter: Arc<Filter>,
+}
+
+impl LogHandle {
+    pub(crate) fn new(filter: Arc<Filter>) -> Self {
+        Self { filter }
+    }
+
+    /// Sets the level for targets which are not matched by a directive. This
+    /// replaces any directive without a target, such as `info` in
+    /// `info,my_crate::net=debug`, while directives for targets are kept.
+    pub fn set_level(&self, level: Level) {
+        self.filter.update(|state| {
+            state.level = level.to_level_filter();
+            state.directives.remove_default();
+        });
+    }
+
+    /// Replace the directives, see `Directives` for the syntax
+    pub fn set_directives(&self, directives: Directives) {
+        self.filter.update(|state| state.directives = directives);
+    }
+
+    /// The most verbose level which is currently enabled for any target
+    pub fn max_level(&self) -> LevelFilter {
+        self.filter.max_level()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update() {
+        let previous = log::max_level();
+        let filter = Arc::new(Filter::new(Level::Info, Directives::default()));
+        let handle = LogHandle::new(filter.clone());
+        let enabled = |level, target| {
+            let metadata = Metadata::builder().level(level).target(target).build();
+            filter.enabled(&metadata)
+        };
+
+        assert!(!enabled(Level::Debug, "app"));
+        handle.set_level(Level::Debug);
+        assert!(enabled(Level::Debug, "app"));
+        assert_eq!(log::max_level(), LevelFilter::Debug);
+
+        handle.set_directives("warn,app::net=trace".parse().unwrap());
+        assert!(!enabled(Level::Info, "app"));
+        assert!(enabled(Level::Trace, "app::net"));
+        assert_eq!(handle.max_level(), LevelFilter::Trace);
+        assert_eq!(log::max_level(), LevelFilter::Trace);
+
+        // the level replaces the directive without a target
+        handle.set_level(Level::Error);
+        assert!(!enabled(Level::Warn, "app"));
+        assert!(enabled(Level::Trace, "app::net"));
+
+        log::set_max_level(previous);
+    }
+}
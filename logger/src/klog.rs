@@ -0,0 +1,229 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A command log, which records one entry per request in its own stream,
+//! separate from the diagnostic logs and unaffected by their levels.
+
+use rustcommon_time::{recent_utc, SecondsFormat};
+
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+// the command log installed with `CommandLogger::init()`
+static COMMAND_LOG: OnceLock<CommandLog> = OnceLock::new();
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Used to select how command log entries are written
+pub enum CommandFormat {
+    /// The timestamp and the entry on a single line
+    Text,
+    /// Each entry as the timestamp in nanoseconds since the UNIX epoch, as a
+    /// little endian `u64`, followed by the length of the entry, as a little
+    /// endian `u32`, and then the bytes of the entry
+    Binary,
+}
+
+/// A builder for a command log
+pub struct CommandLogger {
+    output: Box<dyn Write + Send>,
+    buffer: usize,
+    sample: u64,
+    format: CommandFormat,
+}
+
+/// A command log, which writes each sampled entry to a buffered output. The
+/// buffer is written when it is full, when the log is flushed, and when the
+/// log is dropped. Entries are timestamped from the cached view of the clock,
+/// to whole seconds, so `rustcommon_time::refresh_clock()` should be called
+/// periodically, such as by a timer thread.
+pub struct CommandLog {
+    output: Mutex<BufWriter<Box<dyn Write + Send>>>,
+    sample: u64,
+    format: CommandFormat,
+    entries: AtomicU64,
+}
+
+impl CommandLogger {
+    /// Create a command log which writes to the output, such as a `FileSink`
+    pub fn new<W: Write + Send + 'static>(output: W) -> Self {
+        Self {
+            output: Box::new(output),
+            buffer: 64 * 1024,
+            sample: 1,
+            format: CommandFormat::Text,
+        }
+    }
+
+    /// Sets the size of the buffer in bytes. Defaults to 64KiB.
+    pub fn buffer(mut self, bytes: usize) -> Self {
+        self.buffer = bytes;
+        self
+    }
+
+    /// Only write one in every `ratio` entries. Defaults to 1, which writes
+    /// every entry.
+    pub fn sample(mut self, ratio: u64) -> Self {
+        self.sample = ratio.max(1);
+        self
+    }
+
+    /// Sets how entries are written. Defaults to `CommandFormat::Text`.
+    pub fn format(mut self, format: CommandFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn build(self) -> CommandLog {
+        CommandLog {
+            output: Mutex::new(BufWriter::with_capacity(self.buffer, self.output)),
+            sample: self.sample,
+            format: self.format,
+            entries: AtomicU64::new(0),
+        }
+    }
+
+    /// Install the command log, which is then written to by `klog!`. Returns
+    /// the builder if a command log has already been installed. As the
+    /// installed log is never dropped, it should be flushed with
+    /// `command_log()` before the program exits.
+    pub fn init(self) -> Result<(), Self> {
+        let mut builder = Some(self);
+        COMMAND_LOG.get_or_init(|| builder.take().unwrap().build());
+        match builder {
+            Some(builder) => Err(builder),
+            None => Ok(()),
+        }
+    }
+}
+
+impl CommandLog {
+    /// Write an entry, if it is sampled
+    pub fn log(&self, entry: &[u8]) {
+        if self.sampled() {
+            self.write(entry);
+        }
+    }
+
+    /// Counts an entry and returns whether it is sampled, so that an entry
+    /// which is not sampled need not be formatted. An entry for which this
+    /// returns `true` should then be written with `write()`.
+    pub fn sampled(&self) -> bool {
+        self.entries
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.sample)
+    }
+
+    /// Write an entry without sampling it. Errors writing to the output are
+    /// ignored, as there is nowhere to report them.
+    pub fn write(&self, entry: &[u8]) {
+        let timestamp = recent_utc();
+        if let Ok(mut output) = self.output.lock() {
+            let _ = match self.format {
+                CommandFormat::Text => write!(
+                    output,
+                    "{} ",
+                    timestamp.to_rfc3339_opts(SecondsFormat::Secs, false)
+                )
+                .and_then(|()| output.write_all(entry))
+                .and_then(|()| output.write_all(b"\n")),
+                CommandFormat::Binary => output
+                    .write_all(&(timestamp.unix_timestamp_nanos() as u64).to_le_bytes())
+                    .and_then(|()| output.write_all(&(entry.len() as u32).to_le_bytes()))
+                    .and_then(|()| output.write_all(entry)),
+            };
+        }
+    }
+
+    /// Write any buffered entries to the output
+    pub fn flush(&self) {
+        if let Ok(mut output) = self.output.lock() {
+            let _ = output.flush();
+        }
+    }
+}
+
+/// Returns the command log installed with `CommandLogger::init()`, if any.
+pub fn command_log() -> Option<&'static CommandLog> {
+    COMMAND_LOG.get()
+}
+
+#[macro_export]
+/// Write a formatted entry to the command log, if one has been installed.
+/// The entry is only formatted if it is sampled.
+///
+/// ```
+/// # #[macro_use] extern crate rustcommon_logger;
+/// # fn main() {
+/// klog!("get {} {}", "key", 0);
+/// # }
+/// ```
+macro_rules! klog {
+    ($($arg:tt)+) => {
+        if let Some(log) = $crate::command_log() {
+            if log.sampled() {
+                log.write(format!($($arg)+).as_bytes());
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn text() {
+        let output = Output::default();
+        let log = CommandLogger::new(output.clone()).sample(2).build();
+        for entry in &["get a", "get b", "set c"] {
+            log.log(entry.as_bytes());
+        }
+        // nothing is written until the buffer is flushed
+        assert!(output.0.lock().unwrap().is_empty());
+        log.flush();
+
+        let bytes = output.0.lock().unwrap().clone();
+        let text = String::from_utf8(bytes).unwrap();
+        let entries: Vec<&str> = text.lines().map(|l| l.split_once(' ').unwrap().1).collect();
+        assert_eq!(entries, vec!["get a", "set c"]);
+    }
+
+    #[test]
+    fn sampled() {
+        let log = CommandLogger::new(Output::default()).sample(3).build();
+        let sampled: Vec<bool> = (0..6).map(|_| log.sampled()).collect();
+        assert_eq!(sampled, vec![true, false, false, true, false, false]);
+    }
+
+    #[test]
+    fn binary() {
+        let output = Output::default();
+        let log = CommandLogger::new(output.clone())
+            .format(CommandFormat::Binary)
+            .build();
+        log.log(b"get a");
+        log.flush();
+
+        let bytes = output.0.lock().unwrap().clone();
+        assert_eq!(bytes.len(), 8 + 4 + 5);
+        assert_eq!(&bytes[8..12], &5_u32.to_le_bytes());
+        assert_eq!(&bytes[12..], b"get a");
+    }
+}
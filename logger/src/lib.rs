@@ -6,42 +6,163 @@
 #![macro_use]
 extern crate log;
 
-use rustcommon_time::{now_utc, SecondsFormat};
+mod directives;
+mod file;
+mod format;
+mod handle;
+mod klog;
+mod metrics;
+mod queue;
+mod ratelimit;
+mod sink;
+mod timestamp;
+
+pub use directives::{Directives, ParseDirectivesError};
+pub use file::FileSink;
+pub use format::Format;
+pub use handle::LogHandle;
+pub use klog::{command_log, CommandFormat, CommandLog, CommandLogger};
+pub use queue::FullPolicy;
+pub use ratelimit::Callsite;
+pub use sink::Sink;
+pub use timestamp::{InvalidOffsetError, Timestamp};
+
+use handle::Filter;
+use metrics::DROPPED;
+pub use rustcommon_time::SecondsFormat;
+
+use std::sync::Arc;
 
 #[macro_export]
 macro_rules! fatal {
     () => (
         error!();
+        $crate::logger().flush();
         std::process::exit(1);
         );
     ($fmt:expr) => (
         error!($fmt);
+        $crate::logger().flush();
         std::process::exit(1);
         );
     ($fmt:expr, $($arg:tt)*) => (
         error!($fmt, $($arg)*);
+        $crate::logger().flush();
         std::process::exit(1);
         );
 }
 
 pub use log::*;
 
+/// Returns the number of log lines which have been dropped because the queue
+/// of an asynchronous logger was full.
+pub fn dropped() -> u64 {
+    DROPPED.value()
+}
+
 pub struct Logger {
     label: Option<&'static str>,
+    metrics_prefix: String,
     level: Level,
+    directives: Directives,
+    format: Format,
+    timestamp: Timestamp,
+    capacity: Option<usize>,
+    policy: FullPolicy,
+    file: Option<FileSink>,
+    sinks: Vec<Sink>,
+    filter: Arc<Filter>,
 }
 
 impl Logger {
     pub fn new() -> Self {
         Logger {
             label: None,
+            metrics_prefix: "log".to_string(),
             level: Level::Info,
+            directives: Directives::default(),
+            format: Format::Text,
+            timestamp: Timestamp::new(),
+            capacity: None,
+            policy: FullPolicy::Drop,
+            file: None,
+            sinks: Vec::new(),
+            filter: Arc::new(Filter::new(Level::Info, Directives::default())),
+        }
+    }
+
+    /// Install the logger, returning a handle which may be used to change
+    /// its levels at runtime.
+    pub fn init(mut self) -> Result<LogHandle, SetLoggerError> {
+        let filter = Filter::new(self.level, std::mem::take(&mut self.directives));
+        let max_level = filter.max_level();
+        self.filter = Arc::new(filter);
+        let handle = LogHandle::new(self.filter.clone());
+        if self.sinks.is_empty() {
+            let sink = match self.file.take() {
+                Some(file) => Sink::file(file),
+                None => Sink::stdout(),
+            };
+            self.sinks.push(sink.format(self.format));
+        }
+        if let Some(capacity) = self.capacity {
+            let policy = self.policy;
+            self.sinks = self
+                .sinks
+                .drain(..)
+                .map(|sink| sink.asynchronous(capacity, policy))
+                .collect();
         }
+        self.timestamp.start();
+        let metrics_prefix = std::mem::take(&mut self.metrics_prefix);
+        log::set_boxed_logger(Box::new(self))?;
+        metrics::register(&metrics_prefix);
+        log::set_max_level(max_level);
+        Ok(handle)
     }
 
-    pub fn init(self) -> Result<(), SetLoggerError> {
-        let level = self.level;
-        log::set_boxed_logger(Box::new(self)).map(|()| log::set_max_level(level.to_level_filter()))
+    /// Add a sink which log lines are written to. When sinks are added, they
+    /// replace the default output selected with `file()` and `format()`.
+    /// Records must be enabled by the logger level and directives before
+    /// they are offered to the sinks, so the logger level should be at least
+    /// as verbose as the most verbose sink.
+    pub fn sink(mut self, sink: Sink) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Write log lines to a file instead of stdout, unless sinks are added
+    pub fn file(mut self, file: FileSink) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Write log lines from a background thread instead of the thread which
+    /// logs them. Lines are formatted by the logging thread and held in a
+    /// queue of up to `capacity` lines until they are written, with a queue
+    /// and background thread for each sink. Pending lines are written when
+    /// the logger is flushed with `log::logger().flush()`, which should be
+    /// done before the program exits.
+    pub fn asynchronous(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Sets what happens to log lines when the queue of an asynchronous
+    /// logger is full. By default, the lines are dropped and counted, see
+    /// `dropped()`.
+    pub fn full_policy(mut self, policy: FullPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the prefix for the names of the logger's own metrics, which are
+    /// registered with `rustcommon_metrics_v2` when the logger is installed.
+    /// Defaults to `log`, giving `log.records.<level>`, `log.dropped`,
+    /// `log.bytes`, and `log.write_errors`.
+    pub fn metrics_prefix(mut self, prefix: &str) -> Self {
+        self.metrics_prefix = prefix.to_string();
+        self
     }
 
     pub fn label(mut self, label: &'static str) -> Self {
@@ -53,6 +174,38 @@ impl Logger {
         self.level = level;
         self
     }
+
+    /// Sets the level for each target with directives, such as
+    /// `info,my_crate::net=debug,hyper=warn`. A directive without a target
+    /// replaces the level set with `level()`, which otherwise applies to any
+    /// target not matched by a directive. See `Directives` for the syntax.
+    pub fn directives(mut self, directives: Directives) -> Self {
+        self.directives = directives;
+        self
+    }
+
+    /// Sets the directives from an environment variable, such as `RUST_LOG`,
+    /// if it is set. Returns an error if the variable holds invalid
+    /// directives, leaving the caller to decide whether to report it.
+    pub fn env(mut self, name: &str) -> Result<Self, ParseDirectivesError> {
+        if let Ok(value) = std::env::var(name) {
+            self.directives = value.parse()?;
+        }
+        Ok(self)
+    }
+
+    /// Sets the clock which log lines are timestamped with and how the time
+    /// is written. See `Timestamp` for the defaults.
+    pub fn timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Sets how log records are written. Defaults to `Format::Text`.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
 }
 
 impl Default for Logger {
@@ -63,11 +216,15 @@ impl Default for Logger {
 
 impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+        self.filter.enabled(metadata)
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
+        // records which no sink accepts are neither formatted nor counted
+        if self.enabled(record.metadata())
+            && self.sinks.iter().any(|s| s.accepts(record.metadata()))
+        {
+            metrics::records(record.level()).increment();
             let target = if let Some(label) = self.label {
                 match log::max_level() {
                     LevelFilter::Debug | LevelFilter::Trace => {
@@ -78,15 +235,27 @@ impl log::Log for Logger {
             } else {
                 record.target().to_string()
             };
-            println!(
-                "{} {:<5} [{}] {}",
-                now_utc().to_rfc3339_opts(SecondsFormat::Millis, false),
-                record.level(),
-                target,
-                record.args()
-            );
+            let timestamp = self.timestamp.format();
+            // each format is only rendered once, however many sinks use it
+            let (mut text, mut json) = (None, None);
+            for sink in self.sinks.iter().filter(|s| s.accepts(record.metadata())) {
+                let format = sink.line_format();
+                let line = match format {
+                    Format::Text => &mut text,
+                    Format::Json => &mut json,
+                }
+                .get_or_insert_with(|| format.line(&timestamp, &target, record));
+                if !sink.write(line) {
+                    DROPPED.increment();
+                }
+            }
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        ratelimit::flush();
+        for sink in &self.sinks {
+            sink.flush();
+        }
+    }
 }
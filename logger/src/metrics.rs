@@ -0,0 +1,110 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Counters which describe the behavior of the logger itself, registered
+//! with `rustcommon_metrics_v2` under a configurable prefix.
+
+use log::Level;
+use rustcommon_metrics_v2::{dynmetrics, Counter, MetricEntry};
+
+use std::io::Write;
+
+// records accepted by at least one sink at each level, indexed from
+// `Level::Error`
+static RECORDS: [Counter; 5] = [
+    Counter::new(),
+    Counter::new(),
+    Counter::new(),
+    Counter::new(),
+    Counter::new(),
+];
+pub(crate) static DROPPED: Counter = Counter::new();
+static BYTES: Counter = Counter::new();
+static WRITE_ERRORS: Counter = Counter::new();
+
+/// Register the counters as `<prefix>.records.<level>`, `<prefix>.dropped`,
+/// `<prefix>.bytes`, and `<prefix>.write_errors`.
+pub(crate) fn register(prefix: &str) {
+    for level in Level::iter() {
+        let name = format!("{}.records.{}", prefix, level.as_str().to_ascii_lowercase());
+        dynmetrics::register(MetricEntry::new(records(level), name.into()));
+    }
+    for (counter, name) in [
+        (&DROPPED, "dropped"),
+        (&BYTES, "bytes"),
+        (&WRITE_ERRORS, "write_errors"),
+    ] {
+        let name = format!("{}.{}", prefix, name);
+        dynmetrics::register(MetricEntry::new(counter, name.into()));
+    }
+}
+
+pub(crate) fn records(level: Level) -> &'static Counter {
+    &RECORDS[level as usize - 1]
+}
+
+// an output which counts the bytes written to it and any errors
+pub(crate) struct Metered<W>(pub W);
+
+impl<W: Write> Write for Metered<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.0.write(buf) {
+            Ok(written) => {
+                BYTES.add(written as u64);
+                Ok(written)
+            }
+            Err(e) => {
+                WRITE_ERRORS.increment();
+                Err(e)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush().inspect_err(|_| {
+            WRITE_ERRORS.increment();
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustcommon_metrics_v2::metrics;
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn metered() {
+        register("test.log");
+        let value = |name: &str| {
+            metrics()
+                .iter()
+                .find(|entry| entry.name() == name)
+                .and_then(|entry| entry.as_any())
+                .and_then(|any| any.downcast_ref::<Counter>())
+                .map(|counter| counter.value())
+                .unwrap()
+        };
+
+        let (bytes, errors) = (value("test.log.bytes"), value("test.log.write_errors"));
+        Metered(Vec::new()).write_all(b"hello").unwrap();
+        assert!(Metered(Broken).write_all(b"hello").is_err());
+        assert!(value("test.log.bytes") >= bytes + 5);
+        assert!(value("test.log.write_errors") > errors);
+
+        records(Level::Warn).increment();
+        assert!(value("test.log.records.warn") > 0);
+    }
+}
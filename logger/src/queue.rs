@@ -0,0 +1,211 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A bounded queue of formatted log lines which are written to the output by
+//! a background thread, so that logging does not block on slow outputs.
+
+use crossbeam_queue::ArrayQueue;
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{JoinHandle, Thread};
+use std::time::Duration;
+
+// how long to wait before checking the queue again when it is full or while
+// waiting for it to be flushed
+const BACKOFF: Duration = Duration::from_micros(100);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Used to select what happens to a log line when the queue is full
+pub enum FullPolicy {
+    /// Discard the line and count it as dropped
+    Drop,
+    /// Wait for the background thread to make room for the line
+    Block,
+}
+
+// the state shared between the loggers and the background thread
+struct Shared {
+    lines: ArrayQueue<Vec<u8>>,
+    pushed: AtomicU64,
+    written: AtomicU64,
+    shutdown: AtomicBool,
+}
+
+/// The sending side of the queue, which owns the background thread.
+pub(crate) struct Queue {
+    shared: Arc<Shared>,
+    policy: FullPolicy,
+    thread: Thread,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Queue {
+    /// Create a queue which holds up to `capacity` lines and spawn the
+    /// background thread which writes them to the output.
+    pub fn new(capacity: usize, policy: FullPolicy, output: Box<dyn Write + Send>) -> Self {
+        let shared = Arc::new(Shared {
+            lines: ArrayQueue::new(capacity.max(1)),
+            pushed: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            shutdown: AtomicBool::new(false),
+        });
+        let background = shared.clone();
+        let handle = std::thread::Builder::new()
+            .name("logger".to_string())
+            .spawn(move || background.run(output))
+            .expect("failed to spawn logger thread");
+        Self {
+            shared,
+            policy,
+            thread: handle.thread().clone(),
+            handle: Mutex::new(Some(handle)),
+        }
+    }
+
+    /// Add a line to the queue, applying the full queue policy. Returns
+    /// `false` if the line was dropped.
+    pub fn push(&self, line: Vec<u8>) -> bool {
+        let mut line = line;
+        loop {
+            match self.shared.lines.push(line) {
+                Ok(()) => {
+                    self.shared.pushed.fetch_add(1, Ordering::Relaxed);
+                    self.thread.unpark();
+                    return true;
+                }
+                Err(rejected) => match self.policy {
+                    FullPolicy::Drop => return false,
+                    FullPolicy::Block => {
+                        if !self.is_running() {
+                            return false;
+                        }
+                        line = rejected;
+                        self.thread.unpark();
+                        std::thread::sleep(BACKOFF);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Wait until every line queued before this call has been written to the
+    /// output and the output has been flushed.
+    pub fn flush(&self) {
+        let target = self.shared.pushed.load(Ordering::Relaxed);
+        while self.shared.written.load(Ordering::Relaxed) < target && self.is_running() {
+            self.thread.unpark();
+            std::thread::sleep(BACKOFF);
+        }
+    }
+
+    // whether the background thread is still able to write lines
+    fn is_running(&self) -> bool {
+        match self.handle.lock() {
+            Ok(handle) => handle.as_ref().map(|h| !h.is_finished()).unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Relaxed);
+        self.thread.unpark();
+        if let Ok(mut handle) = self.handle.lock() {
+            if let Some(handle) = handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+impl Shared {
+    // write lines as they are queued until shutdown, flushing the output
+    // whenever the queue has been emptied
+    fn run(&self, mut output: Box<dyn Write + Send>) {
+        loop {
+            let mut written = 0;
+            while let Some(line) = self.lines.pop() {
+                // there is nowhere to report a failure to write a log line
+                let _ = output.write_all(&line);
+                written += 1;
+            }
+            if written > 0 {
+                let _ = output.flush();
+                self.written.fetch_add(written, Ordering::Relaxed);
+            } else if self.shutdown.load(Ordering::Relaxed) {
+                return;
+            } else {
+                std::thread::park();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an output which is shared with the test, and which may be blocked
+    #[derive(Clone)]
+    struct Output {
+        bytes: Arc<Mutex<Vec<u8>>>,
+        blocked: Arc<AtomicBool>,
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            while self.blocked.load(Ordering::Relaxed) {
+                std::thread::sleep(BACKOFF);
+            }
+            self.bytes.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn output() -> Output {
+        Output {
+            bytes: Arc::new(Mutex::new(Vec::new())),
+            blocked: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[test]
+    fn flush() {
+        let output = output();
+        let queue = Queue::new(16, FullPolicy::Block, Box::new(output.clone()));
+        for i in 0..100 {
+            assert!(queue.push(format!("{}\n", i).into_bytes()));
+        }
+        queue.flush();
+        let bytes = output.bytes.lock().unwrap().clone();
+        let lines: Vec<String> = String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|l| l.to_string())
+            .collect();
+        assert_eq!(lines.len(), 100);
+        assert_eq!(lines[99], "99");
+    }
+
+    #[test]
+    fn drop_when_full() {
+        let output = output();
+        output.blocked.store(true, Ordering::Relaxed);
+        let queue = Queue::new(4, FullPolicy::Drop, Box::new(output.clone()));
+        let dropped = (0..10).filter(|_| !queue.push(b"line\n".to_vec())).count();
+        // the background thread may have taken one line before blocking
+        assert!(dropped >= 5);
+        output.blocked.store(false, Ordering::Relaxed);
+        queue.flush();
+        let written = output.bytes.lock().unwrap().len() / 5;
+        assert_eq!(written + dropped, 10);
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Per-callsite state for the rate-limited and sampled logging macros.
+
+use log::Level;
+use rustcommon_ratelimiter::Ratelimiter;
+use rustcommon_time::now_unix;
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+// the minimum number of seconds between summaries of suppressed messages
+// from a callsite
+const SUMMARY_INTERVAL: u32 = 10;
+
+// the callsites which have suppressed a message, so that their counts can be
+// reported when the logger is flushed
+static CALLSITES: Mutex<Vec<&'static Callsite>> = Mutex::new(Vec::new());
+
+#[doc(hidden)]
+/// The state for a single invocation of one of the rate-limited or sampled
+/// logging macros, which is held in a static at the callsite.
+pub struct Callsite {
+    target: &'static str,
+    file: &'static str,
+    line: u32,
+    ratelimiter: OnceLock<Ratelimiter>,
+    calls: AtomicU64,
+    suppressed: AtomicU64,
+    // the level of the most recently suppressed message
+    level: AtomicUsize,
+    registered: AtomicBool,
+    // the unix time in seconds after which a summary may be written
+    summary: AtomicU32,
+}
+
+impl Callsite {
+    pub const fn new(target: &'static str, file: &'static str, line: u32) -> Self {
+        Self {
+            target,
+            file,
+            line,
+            ratelimiter: OnceLock::new(),
+            calls: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+            level: AtomicUsize::new(Level::Error as usize),
+            registered: AtomicBool::new(false),
+            summary: AtomicU32::new(0),
+        }
+    }
+
+    /// Allow up to `rate` messages per second. Returns `None` if the message
+    /// should be suppressed, otherwise the number of suppressed messages to
+    /// report in a summary, which is zero when no summary is due.
+    pub fn ratelimit(&'static self, level: Level, rate: u64) -> Option<u64> {
+        let ratelimiter = self
+            .ratelimiter
+            .get_or_init(|| Ratelimiter::new(1, 1, rate.max(1)));
+        if ratelimiter.try_wait().is_ok() {
+            Some(self.summary())
+        } else {
+            self.suppress(level);
+            None
+        }
+    }
+
+    /// Allow one in every `ratio` messages, starting with the first. Returns
+    /// the same as `ratelimit()`.
+    pub fn sample(&'static self, level: Level, ratio: u64) -> Option<u64> {
+        if self
+            .calls
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(ratio.max(1))
+        {
+            Some(self.summary())
+        } else {
+            self.suppress(level);
+            None
+        }
+    }
+
+    // take the count of suppressed messages, if any, along with the level of
+    // the most recently suppressed message
+    fn pending(&self) -> Option<(Level, u64)> {
+        let suppressed = self.suppressed.swap(0, Ordering::Relaxed);
+        let level = Level::iter()
+            .nth(self.level.load(Ordering::Relaxed) - 1)
+            .unwrap_or(Level::Error);
+        (suppressed > 0).then_some((level, suppressed))
+    }
+
+    // count a suppressed message, registering the callsite the first time so
+    // that the count is reported on flush even if no later message is allowed
+    fn suppress(&'static self, level: Level) {
+        self.level.store(level as usize, Ordering::Relaxed);
+        self.suppressed.fetch_add(1, Ordering::Relaxed);
+        if !self.registered.swap(true, Ordering::Relaxed) {
+            CALLSITES
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(self);
+        }
+    }
+
+    // take the count of suppressed messages if a summary is due
+    fn summary(&self) -> u64 {
+        let now = now_unix();
+        let due = self.summary.load(Ordering::Relaxed);
+        if now >= due
+            && self
+                .summary
+                .compare_exchange(
+                    due,
+                    now + SUMMARY_INTERVAL,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+        {
+            self.suppressed.swap(0, Ordering::Relaxed)
+        } else {
+            0
+        }
+    }
+}
+
+// log the count of suppressed messages from each callsite which has any, so
+// that callsites which have gone quiet are still reported
+pub(crate) fn flush() {
+    let callsites: Vec<&'static Callsite> =
+        CALLSITES.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    for callsite in callsites {
+        let (level, suppressed) = match callsite.pending() {
+            Some(pending) => pending,
+            None => continue,
+        };
+        log::log!(
+            target: callsite.target,
+            level,
+            "suppressed {} messages from {}:{}",
+            suppressed,
+            callsite.file,
+            callsite.line
+        );
+    }
+}
+
+#[macro_export]
+/// Log a message at the level, allowing at most `rate` messages per second
+/// from this callsite. Suppressed messages are counted, and the count is
+/// periodically reported after a message which is allowed through. A count
+/// which has not been reported, such as from a callsite which has gone quiet,
+/// is reported when the logger is flushed with `log::logger().flush()`.
+///
+/// ```
+/// # #[macro_use] extern crate rustcommon_logger;
+/// # use rustcommon_logger::Level;
+/// # fn main() {
+/// log_ratelimited!(Level::Warn, 10, "request failed: {}", "timeout");
+/// # }
+/// ```
+macro_rules! log_ratelimited {
+    ($level:expr, $rate:expr, $($arg:tt)+) => {{
+        static CALLSITE: $crate::Callsite =
+            $crate::Callsite::new(module_path!(), file!(), line!());
+        let level = $level;
+        if $crate::log_enabled!(level) {
+            if let Some(suppressed) = CALLSITE.ratelimit(level, $rate) {
+                $crate::log!(level, $($arg)+);
+                if suppressed > 0 {
+                    $crate::log!(
+                        level,
+                        "suppressed {} messages from {}:{}",
+                        suppressed,
+                        file!(),
+                        line!()
+                    );
+                }
+            }
+        }
+    }};
+}
+
+#[macro_export]
+/// Log a message at the level, allowing one in every `ratio` messages from
+/// this callsite. Suppressed messages are counted, and the count is
+/// periodically reported after a message which is allowed through, or when
+/// the logger is flushed, as with `log_ratelimited!`.
+///
+/// ```
+/// # #[macro_use] extern crate rustcommon_logger;
+/// # use rustcommon_logger::Level;
+/// # fn main() {
+/// log_sampled!(Level::Debug, 100, "cache miss for key: {}", "abc");
+/// # }
+/// ```
+macro_rules! log_sampled {
+    ($level:expr, $ratio:expr, $($arg:tt)+) => {{
+        static CALLSITE: $crate::Callsite =
+            $crate::Callsite::new(module_path!(), file!(), line!());
+        let level = $level;
+        if $crate::log_enabled!(level) {
+            if let Some(suppressed) = CALLSITE.sample(level, $ratio) {
+                $crate::log!(level, $($arg)+);
+                if suppressed > 0 {
+                    $crate::log!(
+                        level,
+                        "suppressed {} messages from {}:{}",
+                        suppressed,
+                        file!(),
+                        line!()
+                    );
+                }
+            }
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! error_ratelimited {
+    ($rate:expr, $($arg:tt)+) => ($crate::log_ratelimited!($crate::Level::Error, $rate, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! warn_ratelimited {
+    ($rate:expr, $($arg:tt)+) => ($crate::log_ratelimited!($crate::Level::Warn, $rate, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! info_ratelimited {
+    ($rate:expr, $($arg:tt)+) => ($crate::log_ratelimited!($crate::Level::Info, $rate, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! debug_ratelimited {
+    ($rate:expr, $($arg:tt)+) => ($crate::log_ratelimited!($crate::Level::Debug, $rate, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! trace_ratelimited {
+    ($rate:expr, $($arg:tt)+) => ($crate::log_ratelimited!($crate::Level::Trace, $rate, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! error_sampled {
+    ($ratio:expr, $($arg:tt)+) => ($crate::log_sampled!($crate::Level::Error, $ratio, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! warn_sampled {
+    ($ratio:expr, $($arg:tt)+) => ($crate::log_sampled!($crate::Level::Warn, $ratio, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! info_sampled {
+    ($ratio:expr, $($arg:tt)+) => ($crate::log_sampled!($crate::Level::Info, $ratio, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! debug_sampled {
+    ($ratio:expr, $($arg:tt)+) => ($crate::log_sampled!($crate::Level::Debug, $ratio, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! trace_sampled {
+    ($ratio:expr, $($arg:tt)+) => ($crate::log_sampled!($crate::Level::Trace, $ratio, $($arg)+));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample() {
+        static CALLSITE: Callsite = Callsite::new("test", file!(), line!());
+        let allowed: Vec<Option<u64>> = (0..7).map(|_| CALLSITE.sample(Level::Info, 3)).collect();
+        // the first summary is due immediately, with nothing to report, and
+        // the next is not due until the interval has passed
+        assert_eq!(
+            allowed,
+            vec![Some(0), None, None, Some(0), None, None, Some(0)]
+        );
+        assert_eq!(CALLSITE.suppressed.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn ratelimit() {
+        static CALLSITE: Callsite = Callsite::new("test", file!(), line!());
+        let allowed = (0..100)
+            .filter(|_| CALLSITE.ratelimit(Level::Info, 1).is_some())
+            .count();
+        assert_eq!(allowed, 1);
+        assert_eq!(CALLSITE.suppressed.load(Ordering::Relaxed), 99);
+
+        // a summary is reported once it is due
+        CALLSITE.summary.store(0, Ordering::Relaxed);
+        assert_eq!(CALLSITE.summary(), 99);
+        assert_eq!(CALLSITE.summary(), 0);
+    }
+
+    #[test]
+    fn quiet() {
+        static CALLSITE: Callsite = Callsite::new("test", file!(), line!());
+        // the callsite suppresses messages and then goes quiet, without a
+        // later message to carry the summary
+        for _ in 0..5 {
+            CALLSITE.sample(Level::Warn, 10);
+        }
+
+        // it is registered so that the count is reported on flush, once
+        let registered = CALLSITES
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|callsite| std::ptr::eq(*callsite, &CALLSITE));
+        assert!(registered);
+        assert_eq!(CALLSITE.pending(), Some((Level::Warn, 4)));
+        assert_eq!(CALLSITE.pending(), None);
+    }
+}
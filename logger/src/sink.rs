@@ -0,0 +1,146 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Destinations for log lines, each with its own format and filters.
+
+use crate::directives::matches;
+use crate::metrics::Metered;
+use crate::queue::{FullPolicy, Queue};
+use crate::{FileSink, Format};
+
+use log::{Level, LevelFilter, Metadata};
+
+use std::io::Write;
+use std::sync::Mutex;
+
+// how lines reach the output
+enum Writer {
+    // written by the logging thread
+    Direct(Mutex<Box<dyn Write + Send>>),
+    // written by a background thread
+    Queued(Queue),
+}
+
+/// A destination for log lines, such as stdout or a file. Each sink has its
+/// own format, and may only accept records at or above a level, or from some
+/// targets. Sinks are added to the logger with `Logger::sink()`, and every
+/// record enabled by the logger is written to each sink which accepts it.
+pub struct Sink {
+    writer: Writer,
+    format: Format,
+    level: LevelFilter,
+    targets: Vec<String>,
+}
+
+impl Sink {
+    /// A sink which writes to the provided output. Bytes written and write
+    /// errors are counted in the logger's metrics.
+    pub fn new<W: Write + Send + 'static>(output: W) -> Self {
+        Self {
+            writer: Writer::Direct(Mutex::new(Box::new(Metered(output)))),
+            format: Format::Text,
+            level: LevelFilter::Trace,
+            targets: Vec::new(),
+        }
+    }
+
+    /// A sink which writes to stdout
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+
+    /// A sink which writes to stderr
+    pub fn stderr() -> Self {
+        Self::new(std::io::stderr())
+    }
+
+    /// A sink which writes to a file
+    pub fn file(file: FileSink) -> Self {
+        Self::new(file)
+    }
+
+    /// Sets how records are written to this sink. Defaults to `Format::Text`.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Only accept records at or above the level. By default, every record
+    /// which is enabled by the logger is accepted.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level.to_level_filter();
+        self
+    }
+
+    /// Only accept records from the target and the modules within it. May be
+    /// called more than once to accept several targets.
+    pub fn target(mut self, target: &str) -> Self {
+        self.targets.push(target.to_string());
+        self
+    }
+
+    // move writes to a background thread with a queue of `capacity` lines
+    pub(crate) fn asynchronous(self, capacity: usize, policy: FullPolicy) -> Self {
+        let writer = match self.writer {
+            Writer::Direct(output) => {
+                let output = output.into_inner().unwrap_or_else(|e| e.into_inner());
+                Writer::Queued(Queue::new(capacity, policy, output))
+            }
+            queued => queued,
+        };
+        Self { writer, ..self }
+    }
+
+    pub(crate) fn line_format(&self) -> Format {
+        self.format
+    }
+
+    pub(crate) fn accepts(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+            && (self.targets.is_empty()
+                || self
+                    .targets
+                    .iter()
+                    .any(|target| matches(target, metadata.target())))
+    }
+
+    // write the line, returning `false` if it was dropped
+    pub(crate) fn write(&self, line: &str) -> bool {
+        match &self.writer {
+            Writer::Direct(output) => {
+                if let Ok(mut output) = output.lock() {
+                    let _ = output.write_all(line.as_bytes());
+                }
+                true
+            }
+            Writer::Queued(queue) => queue.push(line.as_bytes().to_vec()),
+        }
+    }
+
+    pub(crate) fn flush(&self) {
+        match &self.writer {
+            Writer::Direct(output) => {
+                if let Ok(mut output) = output.lock() {
+                    let _ = output.flush();
+                }
+            }
+            Writer::Queued(queue) => queue.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts() {
+        let sink = Sink::stderr().level(Level::Warn).target("app::net");
+        let metadata = |level, target| Metadata::builder().level(level).target(target).build();
+        assert!(sink.accepts(&metadata(Level::Error, "app::net::tcp")));
+        assert!(!sink.accepts(&metadata(Level::Info, "app::net")));
+        assert!(!sink.accepts(&metadata(Level::Error, "app")));
+        assert!(Sink::stdout().accepts(&metadata(Level::Trace, "app")));
+    }
+}
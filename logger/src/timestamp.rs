@@ -0,0 +1,227 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! The timestamps written at the start of each log line.
+
+use rustcommon_time::{recent_utc, DateTime, Duration, Instant, SecondsFormat, SystemTime};
+
+use core::fmt;
+
+// offsets must be less than a day in either direction
+const MAX_OFFSET: i32 = 24 * 3600 - 1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Source {
+    WallClock,
+    Uptime(Instant),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Offset {
+    // seconds east of UTC
+    Fixed(i32),
+    // the offset of the system's local time zone
+    Local,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The error returned when an offset from UTC is a day or more
+pub struct InvalidOffsetError {
+    seconds: i32,
+}
+
+impl fmt::Display for InvalidOffsetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid offset from UTC: {} seconds", self.seconds)
+    }
+}
+
+impl std::error::Error for InvalidOffsetError {}
+
+#[derive(Copy, Clone, Debug)]
+/// Used to select the clock which log lines are timestamped with and how the
+/// timestamp is written. By default, the wall clock is read for every line
+/// and written in RFC 3339 format in UTC with millisecond precision, such as
+/// `2021-01-01T00:00:00.000+00:00`.
+pub struct Timestamp {
+    source: Source,
+    cached: bool,
+    precision: SecondsFormat,
+    use_z: bool,
+    offset: Offset,
+}
+
+impl Timestamp {
+    /// Timestamps from the wall clock
+    pub fn new() -> Self {
+        Self {
+            source: Source::WallClock,
+            cached: false,
+            precision: SecondsFormat::Millis,
+            use_z: false,
+            offset: Offset::Fixed(0),
+        }
+    }
+
+    /// Timestamps which are the monotonic time in seconds since the logger
+    /// was initialized, such as `12.345`
+    pub fn uptime() -> Self {
+        Self {
+            source: Source::Uptime(Instant::now()),
+            ..Self::new()
+        }
+    }
+
+    /// Read the cached view of the clock, instead of reading the clock for
+    /// every line. This avoids the cost of reading the clock, but the time is
+    /// only as recent as the last call to `rustcommon_time::refresh_clock()`,
+    /// which should be made periodically, such as by a timer thread. The
+    /// cached wall clock only has a precision of whole seconds.
+    pub fn cached(mut self, cached: bool) -> Self {
+        self.cached = cached;
+        self
+    }
+
+    /// Sets the number of fractional digits of the seconds. Defaults to
+    /// `SecondsFormat::Millis`.
+    pub fn precision(mut self, precision: SecondsFormat) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Write `Z` instead of `+00:00` for wall clock times in UTC
+    pub fn use_z(mut self, use_z: bool) -> Self {
+        self.use_z = use_z;
+        self
+    }
+
+    /// Write wall clock times at a fixed offset from UTC in seconds, such as
+    /// `-8 * 3600`. Returns an error if the offset is a day or more.
+    pub fn utc_offset(mut self, seconds: i32) -> Result<Self, InvalidOffsetError> {
+        if !(-MAX_OFFSET..=MAX_OFFSET).contains(&seconds) {
+            return Err(InvalidOffsetError { seconds });
+        }
+        self.offset = Offset::Fixed(seconds);
+        Ok(self)
+    }
+
+    /// Write wall clock times in the local time zone of the system, which is
+    /// looked up for each line so that changes such as daylight saving time
+    /// are followed. On platforms other than unix, times are written in UTC.
+    pub fn local_offset(mut self) -> Self {
+        self.offset = Offset::Local;
+        self
+    }
+
+    // restart the uptime clock, so that it is measured from initialization
+    pub(crate) fn start(&mut self) {
+        if let Source::Uptime(_) = self.source {
+            self.source = Source::Uptime(Instant::now());
+        }
+    }
+
+    pub(crate) fn format(&self) -> String {
+        match self.source {
+            Source::WallClock => {
+                let now = if self.cached {
+                    recent_utc()
+                } else {
+                    DateTime::from(SystemTime::now())
+                };
+                let offset = match self.offset {
+                    Offset::Fixed(seconds) => seconds,
+                    Offset::Local => local_offset(&now),
+                };
+                // offsets are checked when they are set, so this is only UTC
+                // if the system reports an invalid local offset
+                let now = now.to_offset(offset).unwrap_or(now);
+                now.to_rfc3339_opts(self.precision, self.use_z)
+            }
+            Source::Uptime(start) => {
+                let now = if self.cached {
+                    Instant::recent()
+                } else {
+                    Instant::now()
+                };
+                uptime(now.saturating_duration_since(start), self.precision)
+            }
+        }
+    }
+}
+
+impl Default for Timestamp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the offset of the system's local time zone from UTC at the time, in
+// seconds east of UTC
+#[cfg(unix)]
+fn local_offset(time: &DateTime) -> i32 {
+    let seconds = (time.unix_timestamp_nanos() / 1_000_000_000) as libc::time_t;
+    // safety: `localtime_r` only writes into the provided `tm`, which is
+    // plain data, and returns null on failure
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&seconds, &mut tm).is_null() {
+            0
+        } else {
+            tm.tm_gmtoff as i32
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn local_offset(_: &DateTime) -> i32 {
+    0
+}
+
+// format the duration as seconds with the precision
+fn uptime(elapsed: Duration, precision: SecondsFormat) -> String {
+    let secs = elapsed.as_secs();
+    match precision {
+        SecondsFormat::Secs => format!("{}", secs),
+        SecondsFormat::Millis => format!("{}.{:03}", secs, elapsed.subsec_millis()),
+        SecondsFormat::Micros => format!("{}.{:06}", secs, elapsed.subsec_micros()),
+        SecondsFormat::Nanos => format!("{}.{:09}", secs, elapsed.subsec_nanos()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uptime_precision() {
+        let elapsed = Duration::from_nanos(12_345_678_901);
+        assert_eq!(uptime(elapsed, SecondsFormat::Secs), "12");
+        assert_eq!(uptime(elapsed, SecondsFormat::Millis), "12.345");
+        assert_eq!(uptime(elapsed, SecondsFormat::Micros), "12.345678");
+        assert_eq!(uptime(elapsed, SecondsFormat::Nanos), "12.345678901");
+    }
+
+    #[test]
+    fn wall_clock() {
+        let utc = Timestamp::new().use_z(true).format();
+        assert!(utc.ends_with('Z'));
+        // millisecond precision, as `2021-01-01T00:00:00.000Z`
+        assert_eq!(utc.len(), 24);
+
+        let local = Timestamp::new()
+            .utc_offset(-(5 * 3600 + 1800))
+            .unwrap()
+            .precision(SecondsFormat::Secs)
+            .format();
+        assert!(local.ends_with("-05:30"));
+        assert_eq!(local.len(), 25);
+
+        assert!(Timestamp::new().utc_offset(24 * 3600).is_err());
+        assert!(Timestamp::new().utc_offset(-30 * 3600).is_err());
+
+        // the local offset is written as `+hh:mm` or `-hh:mm`
+        let local = Timestamp::new().local_offset().format();
+        assert_eq!(local.len(), 29);
+    }
+}
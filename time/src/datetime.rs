@@ -7,8 +7,10 @@ use core::fmt::Display;
 use core::fmt::Formatter;
 use core::ops::Add;
 use core::ops::Sub;
-use time::OffsetDateTime;
+use std::time::SystemTime;
+use time::{OffsetDateTime, UtcOffset};
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum SecondsFormat {
     Secs,
     Millis,
@@ -21,11 +23,21 @@ pub struct DateTime {
     pub(crate) inner: OffsetDateTime,
 }
 
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
 impl DateTime {
     pub fn to_rfc3339_opts(&self, seconds_format: SecondsFormat, use_z: bool) -> String {
         let date = self.inner.date();
         let time = self.inner.time();
-        let tz = if use_z { "Z" } else { "+00:00" };
+        let tz = if use_z && self.offset() == 0 {
+            "Z".to_string()
+        } else {
+            self.format("%:z")
+        };
         let seconds = match seconds_format {
             SecondsFormat::Secs => {
                 format!("{:02}", time.second())
@@ -51,6 +63,114 @@ impl DateTime {
             tz
         )
     }
+
+    /// Returns the same instant with the wall-clock time shifted to a fixed
+    /// offset from UTC, expressed in seconds east of UTC. Returns `None` if
+    /// the offset is more than about a day in either direction.
+    pub fn to_offset(&self, seconds: i32) -> Option<DateTime> {
+        let offset = UtcOffset::from_whole_seconds(seconds).ok()?;
+        Some(DateTime {
+            inner: self.inner.to_offset(offset),
+        })
+    }
+
+    /// Returns the offset from UTC in seconds east of UTC
+    pub fn offset(&self) -> i32 {
+        self.inner.offset().whole_seconds()
+    }
+
+    /// Returns the number of nanoseconds since the UNIX epoch
+    pub fn unix_timestamp_nanos(&self) -> i128 {
+        self.inner.unix_timestamp_nanos()
+    }
+
+    /// Format the `DateTime` using a strftime-like pattern. The supported
+    /// conversion specifiers are:
+    ///
+    /// * `%Y` - the year, as four digits
+    /// * `%y` - the year within the century, as two digits
+    /// * `%m` - the month, as two digits
+    /// * `%b` - the abbreviated month name
+    /// * `%d` - the day of the month, as two digits
+    /// * `%j` - the day of the year, as three digits
+    /// * `%a` - the abbreviated weekday name
+    /// * `%H` - the hour, as two digits
+    /// * `%M` - the minute, as two digits
+    /// * `%S` - the second, as two digits
+    /// * `%3f`, `%6f`, `%9f` - the fraction of the second, as milli-, micro-,
+    ///   or nanoseconds
+    /// * `%s` - the number of seconds since the UNIX epoch
+    /// * `%z` - the offset from UTC, as `+hhmm`
+    /// * `%:z` - the offset from UTC, as `+hh:mm`
+    /// * `%Z` - `UTC` for a zero offset, and otherwise the same as `%:z`
+    /// * `%F` - the same as `%Y-%m-%d`
+    /// * `%T` - the same as `%H:%M:%S`
+    /// * `%%` - a literal `%`
+    ///
+    /// Any other specifier is copied to the output unchanged.
+    pub fn format(&self, pattern: &str) -> String {
+        let date = self.inner.date();
+        let time = self.inner.time();
+        let offset = self.offset();
+        let sign = if offset < 0 { '-' } else { '+' };
+        let (offset_hours, offset_minutes) = (offset.abs() / 3600, offset.abs() / 60 % 60);
+
+        let mut result = String::with_capacity(pattern.len() * 2);
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                result.push(c);
+                continue;
+            }
+            let specifier = match chars.next() {
+                Some(specifier) => specifier,
+                None => {
+                    result.push('%');
+                    break;
+                }
+            };
+            let formatted = match specifier {
+                'Y' => format!("{:04}", date.year()),
+                'y' => format!("{:02}", date.year().rem_euclid(100)),
+                'm' => format!("{:02}", date.month() as u8),
+                'b' => MONTHS[date.month() as usize - 1].to_string(),
+                'd' => format!("{:02}", date.day()),
+                'j' => format!("{:03}", date.ordinal()),
+                'a' => WEEKDAYS[date.weekday().number_days_from_monday() as usize].to_string(),
+                'H' => format!("{:02}", time.hour()),
+                'M' => format!("{:02}", time.minute()),
+                'S' => format!("{:02}", time.second()),
+                's' => format!("{}", self.inner.unix_timestamp()),
+                'z' => format!("{}{:02}{:02}", sign, offset_hours, offset_minutes),
+                'Z' if offset == 0 => "UTC".to_string(),
+                'Z' => format!("{}{:02}:{:02}", sign, offset_hours, offset_minutes),
+                'F' => self.format("%Y-%m-%d"),
+                'T' => self.format("%H:%M:%S"),
+                '%' => "%".to_string(),
+                ':' if chars.peek() == Some(&'z') => {
+                    chars.next();
+                    format!("{}{:02}:{:02}", sign, offset_hours, offset_minutes)
+                }
+                '3' | '6' | '9' if chars.peek() == Some(&'f') => {
+                    chars.next();
+                    let digits = specifier.to_digit(10).unwrap_or(9) as usize;
+                    let fraction = format!("{:09}", time.nanosecond());
+                    fraction[..digits].to_string()
+                }
+                _ => format!("%{}", specifier),
+            };
+            result.push_str(&formatted);
+        }
+        result
+    }
+}
+
+impl From<SystemTime> for DateTime {
+    fn from(system: SystemTime) -> Self {
+        DateTime {
+            inner: OffsetDateTime::from(system),
+        }
+    }
 }
 
 impl Add<Duration> for DateTime {
@@ -97,19 +217,36 @@ impl Sub<core::time::Duration> for DateTime {
 
 impl Display for DateTime {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), core::fmt::Error> {
-        let date = self.inner.date();
-        let time = self.inner.time();
+        write!(f, "{}", self.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+}
 
-        write!(
-            f,
-            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
-            date.year(),
-            date.month() as u8,
-            date.day(),
-            time.hour(),
-            time.minute(),
-            time.second(),
-            time.millisecond()
-        )
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format() {
+        let time = DateTime::from(
+            SystemTime::UNIX_EPOCH + core::time::Duration::new(1_600_000_000, 123_456_789),
+        );
+        assert_eq!(format!("{}", time), "2020-09-13T12:26:40.123Z");
+        assert_eq!(time.format("%F %T.%3f %Z"), "2020-09-13 12:26:40.123 UTC");
+        assert_eq!(
+            time.format("%a %d %b %y %j %6f %s %%"),
+            "Sun 13 Sep 20 257 123456 1600000000 %"
+        );
+
+        let shifted = time.to_offset(-(5 * 3600 + 30 * 60)).unwrap();
+        assert_eq!(shifted.format("%H:%M %z %Z"), "06:56 -0530 -05:30");
+        assert_eq!(
+            shifted.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "2020-09-13T06:56:40-05:30"
+        );
+        assert_eq!(shifted.unix_timestamp_nanos(), time.unix_timestamp_nanos());
+        assert!(time.to_offset(2 * 86_400).is_none());
+
+        // unknown specifiers are copied unchanged
+        assert_eq!(time.format("%Q %"), "%Q %");
     }
 }
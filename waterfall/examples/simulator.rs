@@ -78,17 +78,19 @@ pub fn simulate(shape: Shape) {
     };
 
     for scale in [Scale::Linear, Scale::Logarithmic].iter() {
-        for palette in [Palette::Classic, Palette::Ironbow].iter() {
+        for (palette_name, palette) in [
+            ("classic", Palette::Classic),
+            ("ironbow", Palette::Ironbow),
+            ("viridis", Palette::Viridis),
+            ("magma", Palette::Magma),
+        ]
+        .iter()
+        {
             let scale_name = match scale {
                 Scale::Linear => "linear",
                 Scale::Logarithmic => "logarithmic",
             };
 
-            let palette_name = match palette {
-                Palette::Classic => "classic",
-                Palette::Ironbow => "ironbow",
-            };
-
             let filename = format!("{}_{}_{}.png", shape_name, palette_name, scale_name);
 
             WaterfallBuilder::new(&filename)
@@ -98,7 +100,8 @@ pub fn simulate(shape: Shape) {
                 .label(100000, "100000")
                 .scale(*scale)
                 .palette(*palette)
-                .build(&heatmap);
+                .build(&heatmap)
+                .expect("failed to build waterfall");
 
             let filename = format!("{}_{}_{}_smooth.png", shape_name, palette_name, scale_name);
 
@@ -110,7 +113,8 @@ pub fn simulate(shape: Shape) {
                 .scale(*scale)
                 .palette(*palette)
                 .smooth(Some(1.0))
-                .build(&heatmap);
+                .build(&heatmap)
+                .expect("failed to build waterfall");
         }
     }
 }
@@ -0,0 +1,163 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Layout of values along the horizontal axis and automatic axis ticks.
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Used to select how values are laid out along the horizontal axis
+pub enum Axis {
+    /// Give each bucket of the histogram an equal share of the axis
+    Buckets,
+    /// Space values linearly
+    Linear,
+    /// Space values logarithmically
+    Logarithmic,
+}
+
+impl Axis {
+    // the position of a bucket along the axis, given its index and the range
+    // of values it holds
+    pub(crate) fn range(self, index: usize, min: f64, max: f64) -> (f64, f64) {
+        match self {
+            Axis::Buckets => (index as f64, index as f64 + 1.0),
+            Axis::Linear => (min, max),
+            Axis::Logarithmic => ((min + 1.0).ln(), (max + 1.0).ln()),
+        }
+    }
+
+    // the value at a position along the axis, given the bucket which spans
+    // that position
+    pub(crate) fn value(self, position: f64, range: (f64, f64), min: f64, max: f64) -> f64 {
+        match self {
+            Axis::Buckets => {
+                let fraction = (position - range.0) / (range.1 - range.0);
+                min + fraction.clamp(0.0, 1.0) * (max - min)
+            }
+            Axis::Linear => position,
+            Axis::Logarithmic => position.exp() - 1.0,
+        }
+    }
+}
+
+/// Choose roughly `count` values between `min` and `max` to use as ticks. The
+/// linear axis uses evenly spaced round numbers, while the others use powers
+/// of ten.
+pub(crate) fn ticks(axis: Axis, min: u64, max: u64, count: usize) -> Vec<u64> {
+    let count = count.max(2);
+    if axis != Axis::Linear {
+        let decades: Vec<u64> = (0..20)
+            .map(|power| 10_u64.pow(power))
+            .filter(|v| *v >= min && *v <= max)
+            .collect();
+        if decades.len() >= 2 {
+            // skip decades if there are more than requested
+            let skip = decades.len().div_ceil(count);
+            return decades.into_iter().step_by(skip).collect();
+        }
+    }
+
+    if max <= min {
+        return vec![min];
+    }
+
+    // find a round step which yields no more than the requested count
+    let raw = (max - min) as f64 / count as f64;
+    let magnitude = 10_f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap_or(10.0 * magnitude)
+        .max(1.0) as u64;
+
+    let mut result = Vec::new();
+    let mut tick = min.div_ceil(step) * step;
+    while tick <= max {
+        result.push(tick);
+        tick = match tick.checked_add(step) {
+            Some(tick) => tick,
+            None => break,
+        };
+    }
+    result
+}
+
+/// Format a tick value compactly, using a metric suffix when the value is a
+/// whole multiple of one.
+pub(crate) fn format_value(value: u64) -> String {
+    for (suffix, scale) in [
+        ("E", 1_000_000_000_000_000_000),
+        ("P", 1_000_000_000_000_000),
+        ("T", 1_000_000_000_000),
+        ("G", 1_000_000_000),
+        ("M", 1_000_000),
+        ("k", 1_000),
+    ] {
+        if value >= scale && value / scale * scale == value {
+            return format!("{}{}", value / scale, suffix);
+        }
+    }
+    format!("{}", value)
+}
+
+/// Format a fractional value to a few significant digits, using a metric
+/// suffix for large values.
+pub(crate) fn format_float(value: f64) -> String {
+    if value == 0.0 || !value.is_finite() {
+        return "0".to_string();
+    }
+    for (suffix, scale) in [("G", 1e9), ("M", 1e6), ("k", 1e3), ("", 1.0)] {
+        if value >= scale {
+            let scaled = value / scale;
+            let digits = if scaled >= 100.0 {
+                0
+            } else if scaled >= 10.0 {
+                1
+            } else {
+                2
+            };
+            return format!("{:.*}{}", digits, scaled, suffix);
+        }
+    }
+    format!("{:.2e}", value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear() {
+        assert_eq!(
+            ticks(Axis::Linear, 0, 1000, 5),
+            vec![0, 200, 400, 600, 800, 1000]
+        );
+        assert_eq!(ticks(Axis::Linear, 3, 47, 4), vec![20, 40]);
+    }
+
+    #[test]
+    fn logarithmic() {
+        assert_eq!(
+            ticks(Axis::Logarithmic, 0, 1_000_000, 10),
+            vec![1, 10, 100, 1_000, 10_000, 100_000, 1_000_000]
+        );
+        assert_eq!(
+            ticks(Axis::Buckets, 0, 1_000_000, 3),
+            vec![1, 1_000, 1_000_000]
+        );
+        // falls back to round numbers within a single decade
+        assert_eq!(ticks(Axis::Logarithmic, 20, 90, 4), vec![20, 40, 60, 80]);
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(format_value(0), "0");
+        assert_eq!(format_value(1500), "1500");
+        assert_eq!(format_value(2000), "2k");
+        assert_eq!(format_value(10_000_000), "10M");
+        assert_eq!(format_float(0.0), "0");
+        assert_eq!(format_float(1234.5), "1.23k");
+        assert_eq!(format_float(0.00125), "1.25e-3");
+    }
+}
@@ -0,0 +1,515 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Renders a waterfall from heatmap data stored in a file.
+//!
+//! The input is either a heatmap serialized with `SerializedHeatmap::write()`
+//! or a CSV file with one record per line. In a CSV file, records with two
+//! fields, `timestamp,value`, are individual samples. Records with three
+//! fields, `timestamp,value,count`, are a count of samples of the value at
+//! that time. Timestamps are seconds since the UNIX epoch and may be
+//! fractional. A header line, blank lines, and lines starting with `#` are
+//! ignored.
+
+use rustcommon_heatmap::{Duration, Heatmap, Instant};
+use rustcommon_time::DateTime;
+use rustcommon_waterfall::{
+    Palette, Scale, SerializedHeatmap, TimeFormat, WaterfallBuilder, WaterfallError,
+};
+
+use std::io::Read;
+use std::time::UNIX_EPOCH;
+
+// the most windows the heatmap may hold, which bounds the memory used for a
+// file whose timestamps span a long time relative to the resolution
+const MAX_WINDOWS: u64 = 65_536;
+
+const USAGE: &str = "\
+usage: waterfall [options] <input>
+
+Renders a waterfall from a serialized heatmap, or from a CSV file of
+`timestamp,value` samples or `timestamp,value,count` counts of samples. Use
+`-` to read from stdin.
+
+options:
+    -f, --input-format <fmt>   heatmap or csv [default: heatmap for inputs
+                               with a .heatmap extension, otherwise csv]
+    -o, --output <path>        output path, the extension selects the format
+                               [default: waterfall.png]
+    -p, --palette <name>       classic, ironbow, viridis, magma, cividis, or
+                               grayscale [default: classic]
+    -s, --scale <scale>        linear or logarithmic [default: linear]
+        --smooth <sigma>       blur the waterfall before colorizing
+    -l, --label <value[=text]> label the value axis, may be repeated
+        --resolution <time>    duration of each window of a CSV input, such
+                               as 1s or 100ms [default: 1s]
+        --interval <time>      time between time labels [default: automatic]
+        --time-format <format> rfc3339, relative, or a strftime-like pattern
+                               such as %H:%M:%S [default: rfc3339]
+        --utc-offset <offset>  show times at a fixed offset such as -08:00
+                               [default: +00:00]
+        --precision <digits>   significant figures preserved for a CSV input
+                               [default: 2]
+        --max <value>          largest value stored for a CSV input
+                               [default: largest sample]
+        --width <pixels>       resample the value axis to a fixed width
+        --height <pixels>      aggregate windows to a fixed height
+    -h, --help                 print this message";
+
+// the options parsed from the command line
+struct Options {
+    input: String,
+    input_format: Option<InputFormat>,
+    output: String,
+    palette: Palette,
+    scale: Scale,
+    smooth: Option<f32>,
+    labels: Vec<(u64, String)>,
+    resolution: Duration,
+    interval: Option<Duration>,
+    time_format: TimeFormat,
+    utc_offset: i32,
+    precision: u8,
+    max: Option<u64>,
+    width: Option<usize>,
+    height: Option<usize>,
+}
+
+// how the input is read
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum InputFormat {
+    Heatmap,
+    Csv,
+}
+
+// a count of samples of a value at a time, in nanoseconds since the epoch
+#[derive(Debug, PartialEq)]
+struct Record {
+    timestamp: u64,
+    value: u64,
+    count: u64,
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// parse the arguments, returning `None` if help was requested
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut input = None;
+    let mut options = Options {
+        input: String::new(),
+        input_format: None,
+        output: "waterfall.png".to_string(),
+        palette: Palette::Classic,
+        scale: Scale::Linear,
+        smooth: None,
+        labels: Vec::new(),
+        resolution: Duration::from_secs(1),
+        interval: None,
+        time_format: TimeFormat::Rfc3339,
+        utc_offset: 0,
+        precision: 2,
+        max: None,
+        width: None,
+        height: None,
+    };
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        // accept both `--flag value` and `--flag=value`
+        let (flag, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => {
+                (arg[..i].to_string(), Some(arg[i + 1..].to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for {}", flag))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-f" | "--input-format" => options.input_format = Some(parse_input_format(&value()?)?),
+            "-o" | "--output" => options.output = value()?,
+            "-p" | "--palette" => options.palette = parse_palette(&value()?)?,
+            "-s" | "--scale" => options.scale = parse_scale(&value()?)?,
+            "--smooth" => options.smooth = Some(parse_number(&flag, &value()?)?),
+            "-l" | "--label" => options.labels.push(parse_label(&value()?)?),
+            "--resolution" => options.resolution = parse_duration(&flag, &value()?)?,
+            "--interval" => options.interval = Some(parse_duration(&flag, &value()?)?),
+            "--time-format" => options.time_format = parse_time_format(&value()?),
+            "--utc-offset" => options.utc_offset = parse_offset(&value()?)?,
+            "--precision" => options.precision = parse_number(&flag, &value()?)?,
+            "--max" => options.max = Some(parse_number(&flag, &value()?)?),
+            "--width" => options.width = Some(parse_number(&flag, &value()?)?),
+            "--height" => options.height = Some(parse_number(&flag, &value()?)?),
+            _ if flag.starts_with('-') && flag != "-" => {
+                return Err(format!("unknown option: {}", flag))
+            }
+            _ => {
+                if input.replace(arg).is_some() {
+                    return Err("only one input may be provided".to_string());
+                }
+            }
+        }
+    }
+
+    options.input = input.ok_or("no input provided")?;
+    Ok(Some(options))
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", flag, value))
+}
+
+fn parse_input_format(name: &str) -> Result<InputFormat, String> {
+    match name.to_lowercase().as_str() {
+        "heatmap" => Ok(InputFormat::Heatmap),
+        "csv" => Ok(InputFormat::Csv),
+        _ => Err(format!("unknown input format: {}", name)),
+    }
+}
+
+fn parse_palette(name: &str) -> Result<Palette, String> {
+    match name.to_lowercase().as_str() {
+        "classic" => Ok(Palette::Classic),
+        "ironbow" => Ok(Palette::Ironbow),
+        "viridis" => Ok(Palette::Viridis),
+        "magma" => Ok(Palette::Magma),
+        "cividis" => Ok(Palette::Cividis),
+        "grayscale" | "greyscale" => Ok(Palette::Grayscale),
+        _ => Err(format!("unknown palette: {}", name)),
+    }
+}
+
+fn parse_scale(name: &str) -> Result<Scale, String> {
+    match name.to_lowercase().as_str() {
+        "linear" => Ok(Scale::Linear),
+        "log" | "logarithmic" => Ok(Scale::Logarithmic),
+        _ => Err(format!("unknown scale: {}", name)),
+    }
+}
+
+// a label is a value, optionally followed by `=` and the text to show
+fn parse_label(label: &str) -> Result<(u64, String), String> {
+    let (value, text) = match label.find('=') {
+        Some(i) => (&label[..i], &label[i + 1..]),
+        None => (label, label),
+    };
+    Ok((parse_number("--label", value)?, text.to_string()))
+}
+
+fn parse_time_format(format: &str) -> TimeFormat {
+    match format.to_lowercase().as_str() {
+        "rfc3339" => TimeFormat::Rfc3339,
+        "relative" => TimeFormat::Relative,
+        _ => TimeFormat::Pattern(format.to_string()),
+    }
+}
+
+// an offset is `Z`, `UTC`, or a sign followed by hours and optional minutes,
+// such as `+5`, `-08:00`, or `+0530`
+fn parse_offset(text: &str) -> Result<i32, String> {
+    let invalid = || format!("invalid value for --utc-offset: {}", text);
+    if text.eq_ignore_ascii_case("z") || text.eq_ignore_ascii_case("utc") {
+        return Ok(0);
+    }
+    let sign = match text.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(invalid()),
+    };
+    let digits = text[1..].replace(':', "");
+    let (hours, minutes) = match digits.len() {
+        1 | 2 => (digits.as_str(), "0"),
+        4 => digits.split_at(2),
+        _ => return Err(invalid()),
+    };
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(sign * (hours * 3600 + minutes * 60))
+}
+
+// a duration is a whole number followed by a unit of ns, us, ms, s, m, or h
+fn parse_duration(flag: &str, text: &str) -> Result<Duration, String> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = parse_number(flag, number)?;
+    let nanos = match unit {
+        "ns" => 1,
+        "us" => 1_000,
+        "ms" => 1_000_000,
+        "s" | "" => 1_000_000_000,
+        "m" => 60_000_000_000,
+        "h" => 3_600_000_000_000,
+        _ => return Err(format!("unknown unit for {}: {}", flag, unit)),
+    };
+    match number.checked_mul(nanos) {
+        Some(0) => Err(format!("{} must be greater than zero", flag)),
+        Some(nanos) => Ok(Duration::from_nanos(nanos)),
+        None => Err(format!("{} is too large: {}", flag, text)),
+    }
+}
+
+// parse the records of a CSV file, skipping a header line if present
+fn parse_records(text: &str) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_record(line) {
+            Some(record) => records.push(record),
+            None if records.is_empty() && i == 0 => continue,
+            None => return Err(format!("invalid record on line {}: {}", i + 1, line)),
+        }
+    }
+    Ok(records)
+}
+
+fn parse_record(line: &str) -> Option<Record> {
+    let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+    let (timestamp, value, count) = match fields.as_slice() {
+        [timestamp, value] => (timestamp, value, "1"),
+        [timestamp, value, count] => (timestamp, value, *count),
+        _ => return None,
+    };
+    let timestamp: f64 = timestamp.parse().ok()?;
+    if !timestamp.is_finite() || timestamp < 0.0 {
+        return None;
+    }
+    Some(Record {
+        timestamp: (timestamp * 1e9).round() as u64,
+        value: value.parse().ok()?,
+        count: count.parse().ok()?,
+    })
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    if options.input == "-" {
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("failed to read stdin: {}", e))?;
+    } else {
+        bytes = std::fs::read(&options.input)
+            .map_err(|e| format!("failed to read {}: {}", options.input, e))?;
+    }
+
+    let format = options.input_format.unwrap_or_else(|| {
+        if options.input.ends_with(".heatmap") {
+            InputFormat::Heatmap
+        } else {
+            InputFormat::Csv
+        }
+    });
+    match format {
+        InputFormat::Heatmap => {
+            let heatmap = SerializedHeatmap::read(bytes.as_slice()).map_err(|e| e.to_string())?;
+            let builder = builder(&options).anchor(heatmap.origin(), heatmap.start());
+            builder.build(&heatmap).map_err(|e| e.to_string())
+        }
+        InputFormat::Csv => {
+            let text = String::from_utf8(bytes)
+                .map_err(|_| format!("{} is not valid UTF-8", options.input))?;
+            run_csv(&options, &text)
+        }
+    }
+}
+
+// build a heatmap from the records of a CSV file and render it
+fn run_csv(options: &Options, text: &str) -> Result<(), String> {
+    let mut records = parse_records(text)?;
+    if records.is_empty() {
+        return Err(format!("no records in {}", options.input));
+    }
+    records.sort_by_key(|r| r.timestamp);
+
+    // size the heatmap to hold every record, with the first window starting
+    // at the earliest timestamp
+    let first = records[0].timestamp;
+    let last = records[records.len() - 1].timestamp;
+    let resolution = options.resolution.as_nanos() as u64;
+    let windows = (last - first) / resolution + 1;
+    if windows > MAX_WINDOWS {
+        return Err(format!(
+            "{}: the records span {} windows, which is more than {}, use a \
+             larger --resolution",
+            WaterfallError::TooLarge,
+            windows,
+            MAX_WINDOWS
+        ));
+    }
+    let max = options
+        .max
+        .unwrap_or_else(|| records.iter().map(|r| r.value).max().unwrap_or(0))
+        .max(1);
+
+    let start = Instant::now();
+    let mut heatmap = Heatmap::<u64, u64>::new(
+        max,
+        options.precision,
+        Duration::from_nanos(windows * resolution),
+        options.resolution,
+    );
+    for record in &records {
+        let time = start + Duration::from_nanos(record.timestamp - first);
+        heatmap.increment(time, record.value.min(max), record.count);
+    }
+
+    let builder = builder(options).anchor(
+        start,
+        DateTime::from(UNIX_EPOCH + std::time::Duration::from_nanos(first)),
+    );
+    builder.build(&heatmap).map_err(|e| e.to_string())
+}
+
+// configure the waterfall from the options which apply to any input
+fn builder(options: &Options) -> WaterfallBuilder<u64> {
+    let mut builder = WaterfallBuilder::new(&options.output)
+        .palette(options.palette)
+        .scale(options.scale)
+        .smooth(options.smooth)
+        .time_format(options.time_format.clone())
+        .utc_offset(options.utc_offset);
+    for (value, text) in &options.labels {
+        builder = builder.label(*value, text);
+    }
+    if let Some(interval) = options.interval {
+        builder = builder.interval(interval);
+    }
+    if let Some(width) = options.width {
+        builder = builder.width(width);
+    }
+    if let Some(height) = options.height {
+        builder = builder.height(height);
+    }
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records() {
+        let text = "timestamp,value\n# comment\n1.5,100\n\n2,200,3\n";
+        assert_eq!(
+            parse_records(text).unwrap(),
+            vec![
+                Record {
+                    timestamp: 1_500_000_000,
+                    value: 100,
+                    count: 1
+                },
+                Record {
+                    timestamp: 2_000_000_000,
+                    value: 200,
+                    count: 3
+                },
+            ]
+        );
+        assert!(parse_records("1,100\nfoo,bar\n").is_err());
+    }
+
+    #[test]
+    fn too_many_windows() {
+        let input = std::env::temp_dir().join("waterfall-too-many-windows.csv");
+        std::fs::write(&input, "0,100\n1700000000,200\n").unwrap();
+        let args = vec![
+            input.to_string_lossy().to_string(),
+            "--resolution".to_string(),
+            "1ms".to_string(),
+        ];
+        let options = parse_args(args.into_iter()).unwrap().unwrap();
+        let error = run(options).unwrap_err();
+        let _ = std::fs::remove_file(&input);
+        assert!(error.contains("larger --resolution"), "{}", error);
+    }
+
+    #[test]
+    fn serialized_heatmap() {
+        let start = Instant::now();
+        let mut heatmap =
+            Heatmap::<u64, u64>::new(1_000, 2, Duration::from_secs(10), Duration::from_secs(1));
+        heatmap.increment(start, 100, 1);
+        heatmap.increment(start, 500, 2);
+        let serialized = SerializedHeatmap::new(&heatmap).unwrap();
+
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("waterfall-{}.heatmap", std::process::id()));
+        let output = dir.join(format!("waterfall-{}.png", std::process::id()));
+        serialized
+            .write(std::fs::File::create(&input).unwrap())
+            .unwrap();
+        let args = vec![
+            input.to_string_lossy().to_string(),
+            "-o".to_string(),
+            output.to_string_lossy().to_string(),
+        ];
+        let options = parse_args(args.into_iter()).unwrap().unwrap();
+        let result = run(options);
+        let rendered = std::fs::read(&output);
+        let _ = std::fs::remove_file(&input);
+        let _ = std::fs::remove_file(&output);
+        result.unwrap();
+        assert_eq!(&rendered.unwrap()[1..4], b"PNG");
+    }
+
+    #[test]
+    fn args() {
+        let args =
+            "in.csv -o out.svg --palette=viridis -s log -l 1000=1ms -l 10 --resolution 100ms"
+                .split(' ')
+                .map(|s| s.to_string());
+        let options = parse_args(args).unwrap().unwrap();
+        assert_eq!(options.input, "in.csv");
+        assert_eq!(options.output, "out.svg");
+        assert_eq!(
+            options.labels,
+            vec![(1000, "1ms".to_string()), (10, "10".to_string())]
+        );
+        assert_eq!(options.resolution, Duration::from_millis(100));
+        assert_eq!(options.input_format, None);
+        let args = vec!["-f".to_string(), "heatmap".to_string(), "-".to_string()];
+        let options = parse_args(args.into_iter()).unwrap().unwrap();
+        assert_eq!(options.input_format, Some(InputFormat::Heatmap));
+
+        assert!(parse_args(vec!["--help".to_string()].into_iter())
+            .unwrap()
+            .is_none());
+        assert!(parse_args(vec!["--bogus".to_string()].into_iter()).is_err());
+        assert!(parse_duration("--interval", "5 fortnights").is_err());
+        assert_eq!(parse_offset("-08:00"), Ok(-8 * 3600));
+        assert_eq!(parse_offset("+0530"), Ok(5 * 3600 + 30 * 60));
+        assert_eq!(parse_offset("Z"), Ok(0));
+        assert!(parse_offset("0800").is_err());
+    }
+}
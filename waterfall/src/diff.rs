@@ -0,0 +1,256 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Renders the signed difference between two heatmaps as a waterfall.
+
+use crate::layout::Overlay;
+use crate::source::Frame;
+use crate::{axis, layout, raster, svg};
+use crate::{Format, Palette, Scale, WaterfallBuilder, WaterfallError, WindowSource};
+
+use image::{Luma, RgbImage};
+
+use core::hash::Hash;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Summary statistics describing where two heatmaps diverge. Divergence is
+/// the total variation distance between two distributions, which ranges from
+/// 0.0 when they are identical to 1.0 when they do not overlap.
+pub struct DiffSummary {
+    /// The total number of samples in the baseline
+    pub baseline: f64,
+    /// The total number of samples in the comparison
+    pub comparison: f64,
+    /// The largest absolute difference in count per unit of width for any
+    /// bucket of any window
+    pub max_difference: f64,
+    /// The divergence of each window, from oldest to newest, or `None` if
+    /// either window has no samples
+    pub windows: Vec<Option<f64>>,
+    /// The divergence of the distributions summed across all windows
+    pub overall: f64,
+    /// The index and divergence of the window which diverges the most
+    pub max_divergence: Option<(usize, f64)>,
+}
+
+impl<Value> WaterfallBuilder<Value>
+where
+    Value: Eq + Hash + Copy,
+    u64: From<Value>,
+{
+    /// Generate a waterfall of the difference of the comparison from the
+    /// baseline and write it to the output path. Both sources must have the
+    /// same bucket layout and number of windows, which are paired from oldest
+    /// to newest. Buckets where the comparison is higher are drawn towards
+    /// the top of the palette, and those where it is lower towards the
+    /// bottom, with the diverging palette used unless another is set.
+    /// Percentile traces are not drawn.
+    pub fn build_diff<A, B>(
+        self,
+        baseline: &A,
+        comparison: &B,
+    ) -> Result<DiffSummary, WaterfallError>
+    where
+        A: WindowSource<Value> + ?Sized,
+        B: WindowSource<Value> + ?Sized,
+    {
+        let (buf, overlay, summary) = self.diff(baseline, comparison)?;
+        match self.output_format() {
+            Format::Image => raster::draw(&buf, &overlay)?.save(&self.output)?,
+            Format::Svg => std::fs::write(&self.output, svg::render(&buf, &overlay))?,
+            Format::Html => return Err(WaterfallError::Unsupported),
+        }
+        Ok(summary)
+    }
+
+    /// Render the difference of the comparison from the baseline into an
+    /// in-memory raster image, returning it along with the summary.
+    pub fn render_diff<A, B>(
+        &self,
+        baseline: &A,
+        comparison: &B,
+    ) -> Result<(RgbImage, DiffSummary), WaterfallError>
+    where
+        A: WindowSource<Value> + ?Sized,
+        B: WindowSource<Value> + ?Sized,
+    {
+        let (buf, overlay, summary) = self.diff(baseline, comparison)?;
+        Ok((raster::draw(&buf, &overlay)?, summary))
+    }
+
+    // colorize the differences and lay out their annotations
+    fn diff<A, B>(
+        &self,
+        baseline: &A,
+        comparison: &B,
+    ) -> Result<(RgbImage, Overlay, DiffSummary), WaterfallError>
+    where
+        A: WindowSource<Value> + ?Sized,
+        B: WindowSource<Value> + ?Sized,
+    {
+        let a = self.snapshot(baseline)?;
+        let b = self.snapshot(comparison)?;
+        if a.values != b.values || a.rows() != b.rows() {
+            return Err(WaterfallError::Mismatch);
+        }
+
+        let summary = summarize(&a, &b);
+
+        // the signed difference of each bucket
+        let mut differences = Vec::with_capacity(a.counts.len());
+        let mut max_difference: f64 = 0.0;
+        for y in 0..a.rows() {
+            for (x, (before, after)) in a.row(y).iter().zip(b.row(y)).enumerate() {
+                let difference = self.signed_weight((after - before) / a.widths[x]);
+                max_difference = max_difference.max(difference.abs());
+                differences.push(difference);
+            }
+        }
+
+        // center the differences within the palette
+        let mut weights = crate::weights(&a)?;
+        for (i, difference) in differences.iter().enumerate() {
+            let position = if max_difference > 0.0 {
+                0.5 + 0.5 * difference / max_difference
+            } else {
+                0.5
+            };
+            let (x, y) = (i % a.columns(), i / a.columns());
+            weights.put_pixel(x as u32, y as u32, Luma([position as f32]));
+        }
+        let colors = self.colors(Palette::Diverging);
+        let buf = layout::orient(self.paint(weights, &colors), self.orientation);
+
+        // label the scale with the difference at each position
+        let mut annotations = self.annotations(&b, 0.0);
+        annotations.traces.clear();
+        annotations.colors = colors.into_owned();
+        annotations.scale_title =
+            format!("\u{394}count/{}", self.unit.as_deref().unwrap_or("value"));
+        annotations.scale = [0.0, 0.25, 0.5, 0.75, 1.0]
+            .iter()
+            .map(|position| {
+                let difference = (position * 2.0 - 1.0) * max_difference;
+                let magnitude = match self.scale {
+                    Scale::Linear => difference.abs(),
+                    Scale::Logarithmic => difference.abs().exp2() - 1.0,
+                };
+                let sign = if difference < 0.0 {
+                    "-"
+                } else if difference > 0.0 {
+                    "+"
+                } else {
+                    ""
+                };
+                (
+                    *position,
+                    format!("{}{}", sign, axis::format_float(magnitude)),
+                )
+            })
+            .collect();
+        let overlay = annotations.place(self.orientation, self.layout, self.font_size);
+
+        Ok((buf, overlay, summary))
+    }
+
+    // map a signed difference in weight according to the scale, preserving
+    // the sign
+    fn signed_weight(&self, difference: f64) -> f64 {
+        match self.scale {
+            Scale::Linear => difference,
+            Scale::Logarithmic => difference.signum() * (1.0 + difference.abs()).log2(),
+        }
+    }
+}
+
+// calculate the summary statistics for a pair of snapshots
+fn summarize(a: &Frame, b: &Frame) -> DiffSummary {
+    let mut max_difference: f64 = 0.0;
+    let mut windows = Vec::with_capacity(a.rows());
+    let mut a_overall = vec![0.0; a.columns()];
+    let mut b_overall = vec![0.0; b.columns()];
+
+    for y in 0..a.rows() {
+        let (a_row, b_row) = (a.row(y), b.row(y));
+        for x in 0..a.columns() {
+            let difference = (b_row[x] - a_row[x]).abs() / a.widths[x];
+            max_difference = max_difference.max(difference);
+            a_overall[x] += a_row[x];
+            b_overall[x] += b_row[x];
+        }
+        windows.push(divergence(a_row, b_row));
+    }
+
+    let max_divergence = windows
+        .iter()
+        .enumerate()
+        .filter_map(|(i, d)| d.map(|d| (i, d)))
+        .fold(None, |max: Option<(usize, f64)>, (i, d)| match max {
+            Some((_, m)) if m >= d => max,
+            _ => Some((i, d)),
+        });
+
+    DiffSummary {
+        baseline: a_overall.iter().sum(),
+        comparison: b_overall.iter().sum(),
+        max_difference,
+        windows,
+        overall: divergence(&a_overall, &b_overall).unwrap_or(0.0),
+        max_divergence,
+    }
+}
+
+// the total variation distance between two distributions
+fn divergence(a: &[f64], b: &[f64]) -> Option<f64> {
+    let a_total: f64 = a.iter().sum();
+    let b_total: f64 = b.iter().sum();
+    if a_total <= 0.0 || b_total <= 0.0 {
+        return None;
+    }
+    let distance: f64 = a
+        .iter()
+        .zip(b)
+        .map(|(a, b)| (a / a_total - b / b_total).abs())
+        .sum();
+    Some(0.5 * distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::heatmap;
+    use rustcommon_heatmap::{Duration, Heatmap};
+
+    #[test]
+    fn diverges() {
+        let a = heatmap(&[(10, 2)]);
+        let b = heatmap(&[(10, 1), (50, 1)]);
+
+        let (image, summary) = WaterfallBuilder::new("diff.png")
+            .render_diff(&a, &b)
+            .unwrap();
+        assert_eq!(image.width() as usize, a.buckets());
+        assert_eq!(summary.baseline, 2.0);
+        assert_eq!(summary.comparison, 2.0);
+        assert_eq!(summary.overall, 0.5);
+        let (window, divergence) = summary.max_divergence.unwrap();
+        assert_eq!(divergence, 0.5);
+        assert_eq!(summary.windows[window], Some(0.5));
+        assert_eq!(summary.max_difference, 1.0);
+
+        // a heatmap does not diverge from itself
+        let (_, summary) = WaterfallBuilder::new("diff.png")
+            .render_diff(&a, &a)
+            .unwrap();
+        assert_eq!(summary.overall, 0.0);
+        assert_eq!(summary.max_difference, 0.0);
+
+        // the bucket layouts must match
+        let c = Heatmap::<u64, u64>::new(10_000, 2, Duration::new(10, 0), Duration::new(1, 0));
+        assert!(matches!(
+            WaterfallBuilder::new("diff.png").render_diff(&a, &c),
+            Err(WaterfallError::Mismatch)
+        ));
+    }
+}
@@ -0,0 +1,29 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum WaterfallError {
+    #[error("heatmap contains no windows")]
+    Empty,
+    #[error("heatmap is too large to render")]
+    TooLarge,
+    #[error("heatmaps have different bucket layouts")]
+    Mismatch,
+    #[error("output format is not supported for this waterfall")]
+    Unsupported,
+    #[error("no heatmap metric named {0}")]
+    UnknownMetric(String),
+    #[error("palette requires at least two stops within 0.0 to 1.0")]
+    InvalidPalette,
+    #[error("invalid serialized heatmap: {0}")]
+    InvalidHeatmap(String),
+    #[error("failed to load font")]
+    Font,
+    #[error("failed to encode image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("failed to write output: {0}")]
+    Io(#[from] std::io::Error),
+}
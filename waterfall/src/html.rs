@@ -0,0 +1,271 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Renders a waterfall as a self-contained interactive HTML document. The
+//! counts are embedded in the document and drawn by an inline script, which
+//! provides tooltips, zooming on a range of time, and switching between the
+//! linear and logarithmic color scales without any external assets.
+
+use crate::source::Frame;
+use crate::svg::escape;
+use crate::ColorRgb;
+
+use core::fmt::Write;
+
+/// Everything needed to draw the waterfall in the browser
+pub(crate) struct Document<'a> {
+    /// The title of the document
+    pub title: &'a str,
+    /// The snapshot to embed
+    pub frame: &'a Frame,
+    /// The wall-clock time at the start of each row, followed by the end of
+    /// the last row
+    pub times: Vec<String>,
+    /// The palette, from lowest to highest weight
+    pub colors: &'a [ColorRgb],
+    /// Whether the logarithmic scale is initially selected
+    pub logarithmic: bool,
+    /// The unit of the values, if known
+    pub unit: Option<&'a str>,
+}
+
+impl<'a> Document<'a> {
+    /// Render the document
+    pub fn render(&self) -> String {
+        let frame = self.frame;
+
+        let mut data = String::new();
+        let _ = write!(data, "{{\"values\":[");
+        join(&mut data, frame.values.iter().map(|v| v.to_string()));
+        let _ = write!(data, "],\"widths\":[");
+        join(&mut data, frame.widths.iter().map(|w| number(*w)));
+        let _ = write!(data, "],\"counts\":[");
+        join(&mut data, frame.counts.iter().map(|c| number(*c)));
+        let _ = write!(data, "],\"times\":[");
+        join(&mut data, self.times.iter().map(|t| string(t)));
+        let _ = write!(data, "],\"colors\":[");
+        join(
+            &mut data,
+            self.colors
+                .iter()
+                .map(|c| string(&format!("rgb({},{},{})", c.r, c.g, c.b))),
+        );
+        let _ = write!(
+            data,
+            "],\"logarithmic\":{},\"unit\":{}}}",
+            self.logarithmic,
+            string(self.unit.unwrap_or(""))
+        );
+
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ background: #000; color: #fff; font-family: "DejaVu Sans Mono", monospace; }}
+canvas {{ image-rendering: pixelated; cursor: crosshair; display: block; margin-top: 8px; }}
+#tip {{ position: fixed; pointer-events: none; display: none; white-space: pre; background: rgba(0, 0, 0, 0.85); border: 1px solid #fff; padding: 4px; }}
+</style>
+</head>
+<body>
+<div>
+<label><input type="checkbox" id="log"> logarithmic scale</label>
+<button id="reset">reset zoom</button>
+<span>drag across the waterfall to zoom on a range of time</span>
+</div>
+<canvas id="waterfall"></canvas>
+<div id="tip"></div>
+<script>
+const data = {data};
+{script}
+</script>
+</body>
+</html>
+"#,
+            title = escape(self.title),
+            data = data.replace("</", "<\\/"),
+            script = SCRIPT
+        );
+        html
+    }
+}
+
+// the script which draws the waterfall and handles interaction
+const SCRIPT: &str = r#"(function () {
+  const canvas = document.getElementById("waterfall");
+  const context = canvas.getContext("2d");
+  const tip = document.getElementById("tip");
+  const log = document.getElementById("log");
+  const columns = data.values.length;
+  const rows = data.times.length - 1;
+  const unit = data.unit ? " " + data.unit : "";
+  let first = 0;
+  let last = rows;
+  let drag = null;
+  log.checked = data.logarithmic;
+
+  function count(x, y) {
+    return data.counts[y * columns + x];
+  }
+
+  function weight(x, y) {
+    const w = count(x, y) / data.widths[x];
+    if (!log.checked) {
+      return w;
+    }
+    return w > 1 ? Math.log2(w) : 0;
+  }
+
+  function format(n) {
+    return Number.isInteger(n) ? n.toString() : n.toFixed(3);
+  }
+
+  function draw() {
+    const height = last - first;
+    canvas.width = columns;
+    canvas.height = height;
+    const scale = Math.max(1, Math.floor(1000 / columns));
+    canvas.style.width = columns * scale + "px";
+    canvas.style.height = Math.max(height, Math.min(height * scale, 800)) + "px";
+
+    let max = 0;
+    for (let y = first; y < last; y++) {
+      for (let x = 0; x < columns; x++) {
+        max = Math.max(max, weight(x, y));
+      }
+    }
+
+    const image = context.createImageData(columns, height);
+    const colors = data.colors.map(function (c) {
+      return c.match(/\d+/g).map(Number);
+    });
+    for (let y = first; y < last; y++) {
+      for (let x = 0; x < columns; x++) {
+        const position = max > 0 ? Math.min(1, weight(x, y) / max) : 0;
+        const color = colors[Math.round(position * (colors.length - 1))];
+        const offset = ((y - first) * columns + x) * 4;
+        image.data[offset] = color[0];
+        image.data[offset + 1] = color[1];
+        image.data[offset + 2] = color[2];
+        image.data[offset + 3] = 255;
+      }
+    }
+    context.putImageData(image, 0, 0);
+  }
+
+  function cell(event) {
+    const rect = canvas.getBoundingClientRect();
+    const x = Math.floor((event.clientX - rect.left) / rect.width * columns);
+    const y = first + Math.floor((event.clientY - rect.top) / rect.height * (last - first));
+    return [Math.min(Math.max(x, 0), columns - 1), Math.min(Math.max(y, first), last - 1)];
+  }
+
+  canvas.addEventListener("mousemove", function (event) {
+    const [x, y] = cell(event);
+    const max = data.values[x];
+    const min = Math.max(0, max + 1 - data.widths[x]);
+    tip.textContent = "time:  " + data.times[y] + " - " + data.times[y + 1] +
+      "\nvalue: " + format(min) + " - " + format(max) + unit +
+      "\ncount: " + format(count(x, y));
+    tip.style.left = event.clientX + 12 + "px";
+    tip.style.top = event.clientY + 12 + "px";
+    tip.style.display = "block";
+  });
+
+  canvas.addEventListener("mouseleave", function () {
+    tip.style.display = "none";
+  });
+
+  canvas.addEventListener("mousedown", function (event) {
+    drag = cell(event)[1];
+  });
+
+  canvas.addEventListener("mouseup", function (event) {
+    if (drag === null) {
+      return;
+    }
+    const y = cell(event)[1];
+    if (y !== drag) {
+      first = Math.min(y, drag);
+      last = Math.max(y, drag) + 1;
+      draw();
+    }
+    drag = null;
+  });
+
+  document.getElementById("reset").addEventListener("click", function () {
+    first = 0;
+    last = rows;
+    draw();
+  });
+
+  log.addEventListener("change", draw);
+
+  draw();
+})();"#;
+
+// append the items separated by commas
+fn join(output: &mut String, items: impl Iterator<Item = String>) {
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        output.push_str(&item);
+    }
+}
+
+// format a number for JSON, which has no representation for non-finite values
+fn number(value: f64) -> String {
+    if !value.is_finite() {
+        "0".to_string()
+    } else if value.fract() == 0.0 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.6}", value)
+    }
+}
+
+// format a JSON string
+fn string(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 2);
+    result.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(result, "\\u{:04x}", c as u32);
+            }
+            _ => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        assert_eq!(number(2.0), "2");
+        assert_eq!(number(0.5), "0.500000");
+        assert_eq!(number(f64::NAN), "0");
+        assert_eq!(string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+
+    #[test]
+    fn title() {
+        // the title is escaped with the same rules as SVG text
+        assert_eq!(
+            escape("<a title=\"x\">&'"),
+            "&lt;a title=&quot;x&quot;&gt;&amp;&apos;"
+        );
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Places the labels, traces, and legends of a waterfall relative to the
+//! colorized data, producing an `Overlay` which is drawn by each backend.
+
+use crate::ColorRgb;
+
+use image::RgbImage;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Used to select the direction in which time runs across the waterfall
+pub enum Orientation {
+    /// Time runs from top to bottom, with values increasing from left to right
+    Vertical,
+    /// Time runs from left to right, with values increasing from bottom to top
+    Horizontal,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Used to select where annotations are placed relative to the data
+pub enum Layout {
+    /// Labels and the percentile legend are drawn over the data
+    Overlay,
+    /// Labels, axis titles, the percentile legend, and a color scale are
+    /// drawn in margins around the data
+    Margins,
+}
+
+// the advance of each character in the monospace label font, relative to the
+// font size
+pub(crate) const CHARACTER_WIDTH: f32 = 0.6;
+
+/// The column of a percentile within each row, if the row has any samples.
+pub(crate) struct Trace {
+    pub label: String,
+    pub color: ColorRgb,
+    pub points: Vec<Option<u32>>,
+}
+
+/// Everything which annotates the data, positioned by column and row of the
+/// frame rather than by pixel.
+pub(crate) struct Annotations {
+    /// The number of columns in the frame
+    pub columns: u32,
+    /// The number of rows in the frame
+    pub rows: u32,
+    /// The column and text of each value label
+    pub values: Vec<(u32, String)>,
+    /// The row and text of each time label
+    pub times: Vec<(u32, String)>,
+    /// The percentile traces
+    pub traces: Vec<Trace>,
+    /// The title for the value axis
+    pub value_title: Option<String>,
+    /// The title for the time axis
+    pub time_title: Option<String>,
+    /// The title for the color scale
+    pub scale_title: String,
+    /// The position (0.0 - 1.0) and text of each label on the color scale
+    pub scale: Vec<(f64, String)>,
+    /// The palette, from lowest to highest weight
+    pub colors: Vec<ColorRgb>,
+}
+
+/// A gridline, from the first point to the second, inclusive
+pub(crate) struct Line {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+/// A single line of text, positioned by its top left corner
+pub(crate) struct Text {
+    pub x: u32,
+    pub y: u32,
+    pub text: String,
+}
+
+/// A filled rectangle
+pub(crate) struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub color: ColorRgb,
+}
+
+/// A percentile trace, which is broken wherever a point is missing
+pub(crate) struct Path {
+    pub color: ColorRgb,
+    pub points: Vec<Option<(u32, u32)>>,
+}
+
+/// The annotations of a waterfall, in pixels, along with the size of the
+/// output and the position of the data within it.
+pub(crate) struct Overlay {
+    /// The width of the output
+    pub width: u32,
+    /// The height of the output
+    pub height: u32,
+    /// The position of the top left corner of the data
+    pub plot: (u32, u32),
+    /// The width and height of the data
+    pub data: (u32, u32),
+    pub gridlines: Vec<Line>,
+    pub text: Vec<Text>,
+    pub rects: Vec<Rect>,
+    pub paths: Vec<Path>,
+    /// The size of the label text
+    pub font_size: f32,
+}
+
+/// Rotate the colorized data to match the orientation. The data is colorized
+/// with time running from top to bottom.
+pub(crate) fn orient(buf: RgbImage, orientation: Orientation) -> RgbImage {
+    match orientation {
+        Orientation::Vertical => buf,
+        Orientation::Horizontal => image::imageops::rotate270(&buf),
+    }
+}
+
+impl Annotations {
+    /// Position the annotations for the provided orientation and layout.
+    pub fn place(&self, orientation: Orientation, layout: Layout, font_size: f32) -> Overlay {
+        let line = (font_size * 1.2).ceil() as u32;
+        let pad = (font_size / 2.0).ceil() as u32;
+        let text_width =
+            |text: &str| (text.chars().count() as f32 * CHARACTER_WIDTH * font_size).ceil() as u32;
+
+        // the size of the data, and the pixel for each column and row
+        let columns = self.columns;
+        let (plot_width, plot_height) = match orientation {
+            Orientation::Vertical => (self.columns, self.rows),
+            Orientation::Horizontal => (self.rows, self.columns),
+        };
+        let point = |column: u32, row: u32| match orientation {
+            Orientation::Vertical => (column, row),
+            Orientation::Horizontal => (row, columns.saturating_sub(column + 1)),
+        };
+
+        // labels along the bottom are positioned by x, and those along the
+        // left side by y
+        let (bottom, left, bottom_title, left_title) = match orientation {
+            Orientation::Vertical => (
+                self.values
+                    .iter()
+                    .map(|(c, t)| (point(*c, 0).0, t))
+                    .collect::<Vec<_>>(),
+                self.times
+                    .iter()
+                    .map(|(r, t)| (point(0, *r).1, t))
+                    .collect::<Vec<_>>(),
+                &self.value_title,
+                &self.time_title,
+            ),
+            Orientation::Horizontal => (
+                self.times
+                    .iter()
+                    .map(|(r, t)| (point(0, *r).0, t))
+                    .collect(),
+                self.values
+                    .iter()
+                    .map(|(c, t)| (point(*c, 0).1, t))
+                    .collect(),
+                &self.time_title,
+                &self.value_title,
+            ),
+        };
+
+        let mut overlay = Overlay {
+            width: plot_width,
+            height: plot_height,
+            plot: (0, 0),
+            data: (plot_width, plot_height),
+            gridlines: Vec::new(),
+            text: Vec::new(),
+            rects: Vec::new(),
+            paths: Vec::new(),
+            font_size,
+        };
+
+        let swatch = line / 2;
+
+        match layout {
+            Layout::Overlay => {
+                for (x, label) in bottom {
+                    overlay.vertical(x, label, x, 0);
+                }
+                for (y, label) in left {
+                    overlay.horizontal(y, label, 0, y + 2);
+                }
+
+                // legend stacked in the top right corner
+                for (i, trace) in self.traces.iter().enumerate() {
+                    let y = i as u32 * line;
+                    let x = plot_width.saturating_sub(swatch);
+                    overlay.swatch(x, y + (line - swatch) / 2, swatch, trace.color);
+                    overlay.text(
+                        x.saturating_sub(swatch + text_width(&trace.label)),
+                        y,
+                        &trace.label,
+                    );
+                }
+            }
+            Layout::Margins => {
+                let widest = |labels: &mut dyn Iterator<Item = u32>| labels.max().unwrap_or(0);
+
+                let top = if left_title.is_some() || !self.traces.is_empty() {
+                    pad + line
+                } else {
+                    pad
+                };
+                let left_margin = pad + widest(&mut left.iter().map(|(_, t)| text_width(t))) + pad;
+                let bottom_margin =
+                    pad + line + if bottom_title.is_some() { line } else { 0 } + pad;
+                let scale_width =
+                    line + pad + widest(&mut self.scale.iter().map(|(_, t)| text_width(t)));
+                let right_margin = pad + scale_width.max(text_width(&self.scale_title)) + pad;
+
+                overlay.width = left_margin + plot_width + right_margin;
+                overlay.height = top + plot_height + bottom_margin;
+                overlay.plot = (left_margin, top);
+
+                // tick labels
+                for (x, label) in bottom {
+                    let center = (left_margin + x).saturating_sub(text_width(label) / 2);
+                    let tx = center.min(overlay.width.saturating_sub(text_width(label)));
+                    overlay.vertical(x, label, tx, top + plot_height + pad / 2);
+                }
+                for (y, label) in left {
+                    let tx = left_margin - pad - text_width(label);
+                    let ty = (top + y).saturating_sub(line / 2);
+                    overlay.horizontal(y, label, tx, ty);
+                }
+
+                // axis titles
+                if let Some(title) = left_title {
+                    overlay.text(pad, pad / 2, title);
+                }
+                if let Some(title) = bottom_title {
+                    let x = (left_margin + plot_width / 2).saturating_sub(text_width(title) / 2);
+                    overlay.text(x, top + plot_height + pad / 2 + line, title);
+                }
+
+                // legend in a single line above the data, aligned to the right
+                let mut x = left_margin + plot_width;
+                for trace in self.traces.iter().rev() {
+                    x = x.saturating_sub(text_width(&trace.label));
+                    overlay.text(x, pad / 2, &trace.label);
+                    x = x.saturating_sub(pad / 2 + swatch);
+                    overlay.swatch(x, pad / 2 + (line - swatch) / 2, swatch, trace.color);
+                    x = x.saturating_sub(pad);
+                }
+
+                // color scale to the right of the data, with the highest
+                // weight at the top
+                let bar = left_margin + plot_width + pad;
+                let max_index = self.colors.len().saturating_sub(1) as f64;
+                let span = plot_height.saturating_sub(1).max(1) as f64;
+                let color = |y: u32| {
+                    let position = 1.0 - y as f64 / span;
+                    self.colors[(position * max_index).round() as usize]
+                };
+                let mut start = 0;
+                for y in 1..=plot_height {
+                    if y == plot_height || color(y) != color(start) {
+                        overlay.rects.push(Rect {
+                            x: bar,
+                            y: top + start,
+                            width: line,
+                            height: y - start,
+                            color: color(start),
+                        });
+                        start = y;
+                    }
+                }
+                for (position, label) in &self.scale {
+                    let y = top + ((1.0 - position) * span).round() as u32;
+                    let ty = y
+                        .saturating_sub(line / 2)
+                        .min(overlay.height.saturating_sub(line));
+                    overlay.text(bar + line + pad, ty, label);
+                }
+                overlay.text(bar, pad / 2, &self.scale_title);
+            }
+        }
+
+        // percentile traces
+        let (x0, y0) = overlay.plot;
+        for trace in &self.traces {
+            overlay.paths.push(Path {
+                color: trace.color,
+                points: trace
+                    .points
+                    .iter()
+                    .enumerate()
+                    .map(|(row, column)| {
+                        column.map(|c| {
+                            let (x, y) = point(c, row as u32);
+                            (x0 + x, y0 + y)
+                        })
+                    })
+                    .collect(),
+            });
+        }
+
+        overlay
+    }
+}
+
+impl Overlay {
+    // a vertical gridline through the data at x, with a label
+    fn vertical(&mut self, x: u32, label: &str, tx: u32, ty: u32) {
+        let (x0, y0) = self.plot;
+        self.gridlines.push(Line {
+            x0: x0 + x,
+            y0,
+            x1: x0 + x,
+            y1: y0 + self.data.1 - 1,
+        });
+        self.text(tx, ty, label);
+    }
+
+    // a horizontal gridline through the data at y, with a label
+    fn horizontal(&mut self, y: u32, label: &str, tx: u32, ty: u32) {
+        let (x0, y0) = self.plot;
+        self.gridlines.push(Line {
+            x0,
+            y0: y0 + y,
+            x1: x0 + self.data.0 - 1,
+            y1: y0 + y,
+        });
+        self.text(tx, ty, label);
+    }
+
+    fn text(&mut self, x: u32, y: u32, text: &str) {
+        self.text.push(Text {
+            x,
+            y,
+            text: text.to_string(),
+        });
+    }
+
+    fn swatch(&mut self, x: u32, y: u32, size: u32, color: ColorRgb) {
+        self.rects.push(Rect {
+            x,
+            y,
+            width: size,
+            height: size,
+            color,
+        });
+    }
+}
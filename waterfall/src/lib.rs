@@ -4,18 +4,40 @@
 
 //! This crate is used to render a waterfall style plot of a heatmap
 
+mod axis;
+mod diff;
+mod error;
+mod html;
+mod layout;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod palettes;
-
-pub use palettes::Palette;
-use rustcommon_time::now_utc;
+mod raster;
+mod serialized;
+mod source;
+mod stream;
+mod svg;
+mod terminal;
+mod timestamps;
+
+pub use axis::Axis;
+pub use diff::DiffSummary;
+pub use error::WaterfallError;
+pub use layout::{Layout, Orientation};
+pub use palettes::{Gradient, Palette};
+pub use serialized::SerializedHeatmap;
+pub use source::{BucketCount, WindowSource};
+pub use stream::WaterfallStream;
+pub use timestamps::TimeFormat;
 
 use image::*;
-use palettes::*;
+use layout::{Annotations, Overlay, Trace};
 use rustcommon_heatmap::*;
-use rusttype::{point, Font, PositionedGlyph, Scale as TypeScale};
+use rustcommon_time::{now_utc, DateTime};
+use source::Frame;
 
 use core::hash::Hash;
-use core::ops::Sub;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::{From, TryInto};
 
@@ -28,40 +50,98 @@ pub enum Scale {
     Logarithmic,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Used to select the type of file the waterfall is written as
+pub enum Format {
+    /// A raster image, encoded according to the extension of the output path
+    Image,
+    /// A scalable vector graphic
+    Svg,
+    /// A self-contained interactive HTML document
+    Html,
+}
+
 pub struct WaterfallBuilder<Value> {
     output: String,
+    format: Option<Format>,
     labels: HashMap<Value, String>,
-    palette: Palette,
-    interval: Duration,
+    palette: Option<Palette>,
+    gradient: Option<Gradient>,
+    interval: Option<Duration>,
+    time_format: TimeFormat,
+    utc_offset: i32,
     scale: Scale,
     smooth: Option<f32>,
+    percentiles: Vec<(f64, ColorRgb)>,
+    axis: Axis,
+    width: Option<usize>,
+    height: Option<usize>,
+    font_size: f32,
+    orientation: Orientation,
+    layout: Layout,
+    value_title: Option<String>,
+    time_title: Option<String>,
+    unit: Option<String>,
+    anchor: Option<(Instant, DateTime)>,
 }
 
 impl<Value> WaterfallBuilder<Value>
 where
-    Value: Eq + Hash,
+    Value: Eq + Hash + Copy,
     u64: From<Value>,
 {
     pub fn new(target: &str) -> Self {
         Self {
             output: target.to_string(),
+            format: None,
             labels: HashMap::new(),
-            palette: Palette::Classic,
-            interval: Duration::new(60, 0),
+            palette: None,
+            gradient: None,
+            interval: None,
+            time_format: TimeFormat::Rfc3339,
+            utc_offset: 0,
             scale: Scale::Linear,
             smooth: None,
+            percentiles: Vec::new(),
+            axis: Axis::Buckets,
+            width: None,
+            height: None,
+            font_size: 25.0,
+            orientation: Orientation::Vertical,
+            layout: Layout::Overlay,
+            value_title: None,
+            time_title: None,
+            unit: None,
+            anchor: None,
         }
     }
 
-    /// Adds a label to the horizontal axis at the specified value
+    /// Adds a label to the horizontal axis at the specified value. If no
+    /// labels are added, ticks are chosen automatically.
     pub fn label(mut self, value: Value, label: &str) -> Self {
         self.labels.insert(value, label.to_string());
         self
     }
 
-    /// Sets the color palette for the waterfall
+    /// Sets the output format for the waterfall. By default, the format is
+    /// selected from the extension of the output path, with `.svg` producing
+    /// a vector image and anything else a raster image.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Sets the color palette for the waterfall. Defaults to the classic
+    /// palette, or the diverging palette for differences.
     pub fn palette(mut self, palette: Palette) -> Self {
-        self.palette = palette;
+        self.palette = Some(palette);
+        self
+    }
+
+    /// Sets a custom palette interpolated from the gradient, which takes
+    /// precedence over the palette set with `palette()`.
+    pub fn gradient(mut self, gradient: Gradient) -> Self {
+        self.gradient = Some(gradient);
         self
     }
 
@@ -71,6 +151,100 @@ where
         self
     }
 
+    /// Sets how values are laid out along the horizontal axis. By default,
+    /// each bucket of the heatmap gets an equal share of the axis.
+    pub fn axis(mut self, axis: Axis) -> Self {
+        self.axis = axis;
+        self
+    }
+
+    /// Sets the width of the waterfall in pixels. The buckets are resampled
+    /// onto the value axis to fit. By default, the waterfall is one pixel
+    /// wide per bucket.
+    pub fn width(mut self, pixels: usize) -> Self {
+        self.width = Some(pixels);
+        self
+    }
+
+    /// Sets the height of the waterfall in pixels. Windows are aggregated
+    /// into rows to fit. By default, the waterfall is one pixel high per
+    /// window.
+    pub fn height(mut self, pixels: usize) -> Self {
+        self.height = Some(pixels);
+        self
+    }
+
+    /// Sets the size of the label text in pixels
+    pub fn font_size(mut self, size: f32) -> Self {
+        self.font_size = size;
+        self
+    }
+
+    /// Sets the direction in which time runs across the waterfall. By
+    /// default, time runs from top to bottom.
+    pub fn orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    /// Sets where labels are placed. By default, labels are drawn over the
+    /// data. Axis titles and the color scale are only drawn in the margins
+    /// layout.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Sets the title for the value axis
+    pub fn value_title(mut self, title: &str) -> Self {
+        self.value_title = Some(title.to_string());
+        self
+    }
+
+    /// Sets the title for the time axis
+    pub fn time_title(mut self, title: &str) -> Self {
+        self.time_title = Some(title.to_string());
+        self
+    }
+
+    /// Sets the unit of the values, which is shown with the value axis title
+    /// and the color scale
+    pub fn unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_string());
+        self
+    }
+
+    /// Sets the interval between time labels. By default, the interval is
+    /// chosen from a set of round durations so that labels do not overlap
+    /// for the span of time covered by the waterfall.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Sets how the time labels are written. Defaults to RFC 3339 with
+    /// millisecond precision.
+    pub fn time_format(mut self, format: TimeFormat) -> Self {
+        self.time_format = format;
+        self
+    }
+
+    /// Sets a fixed offset from UTC, in seconds east of UTC, for the time
+    /// labels. Labels are aligned to round times at this offset. Offsets of
+    /// more than about a day are ignored. Defaults to UTC.
+    pub fn utc_offset(mut self, seconds: i32) -> Self {
+        self.utc_offset = seconds;
+        self
+    }
+
+    /// Sets the wall-clock time of an instant, which the time labels are
+    /// calculated relative to. By default, instants are assumed to have
+    /// happened in the recent past and are converted using the current time.
+    pub fn anchor(mut self, instant: Instant, time: DateTime) -> Self {
+        self.anchor = Some((instant, time));
+        self
+    }
+
     /// Set a smoothing on the waterfall which is applied before colorization
     pub fn smooth(mut self, sigma: Option<f32>) -> Self {
         self.smooth = sigma;
@@ -78,25 +252,38 @@ where
     }
 
     // get the scaled weight for a bucket count / width
-    fn weight(&self, count: u64, width: u64) -> f64 {
+    fn weight(&self, count: f64, width: f64) -> f64 {
         match self.scale {
-            Scale::Linear => count as f64 / width as f64,
-            Scale::Logarithmic => (count as f64 / width as f64).log2(),
+            Scale::Linear => count / width,
+            Scale::Logarithmic => (count / width).log2(),
+        }
+    }
+
+    /// Adds a trace of the specified percentile (0.0 - 100.0) of each window,
+    /// drawn in the provided color. Each trace is identified in a legend.
+    pub fn percentile(mut self, percentile: f64, color: [u8; 3]) -> Self {
+        let [r, g, b] = color;
+        self.percentiles.push((percentile, ColorRgb { r, g, b }));
+        self
+    }
+
+    // get the weight for a bucket relative to the highest weight, constrained
+    // to the range 0.0 to 1.0
+    fn scaled_weight(&self, count: f64, width: f64, max_weight: f64) -> f64 {
+        let scaled_weight = self.weight(count, width) / max_weight;
+        if scaled_weight.is_nan() {
+            0.0
+        } else {
+            scaled_weight.clamp(0.0, 1.0)
         }
     }
 
     // find the bucket with the highest weight
-    fn max_weight<Count>(&self, heatmap: &rustcommon_heatmap::Heatmap<Value, Count>) -> f64
-    where
-        Count: rustcommon_heatmap::Counter + PartialOrd + Indexing,
-        Value: rustcommon_heatmap::Indexing + Sub<Output = Value> + Default + PartialOrd,
-        u64: From<Count>,
-        Count: From<u8>,
-    {
+    fn max_weight(&self, frame: &Frame) -> f64 {
         let mut max_weight = 0.0;
-        for slice in heatmap {
-            for b in slice.histogram() {
-                let weight = self.weight(u64::from(b.count()), u64::from(b.width()));
+        for y in 0..frame.rows() {
+            for (x, count) in frame.row(y).iter().enumerate() {
+                let weight = self.weight(*count, frame.widths[x]);
                 if weight > max_weight {
                     max_weight = weight;
                 }
@@ -105,136 +292,390 @@ where
         max_weight
     }
 
-    /// Generate the waterfall from the provided heatmap
-    pub fn build<Count>(self, heatmap: &rustcommon_heatmap::Heatmap<Value, Count>)
+    /// Generate the waterfall from the provided heatmap and write it to the
+    /// output path.
+    pub fn build<Source>(self, source: &Source) -> Result<(), WaterfallError>
     where
-        Count: rustcommon_heatmap::Counter + PartialOrd + Indexing,
-        Value: rustcommon_heatmap::Indexing + Sub<Output = Value> + Default + PartialOrd,
-        u64: From<Count>,
-        Count: From<u8>,
+        Source: WindowSource<Value> + ?Sized,
     {
-        let now_datetime = now_utc();
-        let now_instant = Instant::now();
+        let frame = self.snapshot(source)?;
+        match self.output_format() {
+            Format::Image => {
+                self.render_frame(&frame)?.save(&self.output)?;
+            }
+            Format::Svg => {
+                std::fs::write(&self.output, self.render_svg(&frame)?)?;
+            }
+            Format::Html => {
+                std::fs::write(&self.output, self.render_html(&frame))?;
+            }
+        }
+        Ok(())
+    }
 
-        let height = heatmap.windows();
-        let width = heatmap.buckets();
+    /// Render the waterfall for the provided heatmap into an in-memory raster
+    /// image, without writing any output.
+    pub fn render<Source>(&self, source: &Source) -> Result<RgbImage, WaterfallError>
+    where
+        Source: WindowSource<Value> + ?Sized,
+    {
+        self.render_frame(&self.snapshot(source)?)
+    }
 
-        let mut buf = RgbImage::new(width.try_into().unwrap(), height.try_into().unwrap());
+    /// Render the waterfall for the provided heatmap and return the bytes of
+    /// the encoded output. Raster images are encoded according to the
+    /// extension of the output path, falling back to PNG.
+    pub fn encode<Source>(&self, source: &Source) -> Result<Vec<u8>, WaterfallError>
+    where
+        Source: WindowSource<Value> + ?Sized,
+    {
+        let frame = self.snapshot(source)?;
+        match self.output_format() {
+            Format::Image => {
+                let format = ImageFormat::from_path(&self.output).unwrap_or(ImageFormat::Png);
+                let mut bytes = Vec::new();
+                DynamicImage::ImageRgb8(self.render_frame(&frame)?).write_to(&mut bytes, format)?;
+                Ok(bytes)
+            }
+            Format::Svg => Ok(self.render_svg(&frame)?.into_bytes()),
+            Format::Html => Ok(self.render_html(&frame).into_bytes()),
+        }
+    }
 
-        // need to know the start time of the heatmap
-        let begin_instant = heatmap.into_iter().next().unwrap().start();
+    // take a snapshot of the source, resampled to the configured size
+    fn snapshot<Source>(&self, source: &Source) -> Result<Frame, WaterfallError>
+    where
+        Source: WindowSource<Value> + ?Sized,
+    {
+        Ok(Frame::new(source)?.resample(self.axis, self.width, self.height))
+    }
+
+    // render the waterfall for a snapshot into a raster image
+    fn render_frame(&self, frame: &Frame) -> Result<RgbImage, WaterfallError> {
+        let buf = layout::orient(self.colorize(frame)?, self.orientation);
+        raster::draw(&buf, &self.overlay(frame))
+    }
+
+    // render the waterfall for a snapshot as an SVG document
+    fn render_svg(&self, frame: &Frame) -> Result<String, WaterfallError> {
+        let buf = layout::orient(self.colorize(frame)?, self.orientation);
+        Ok(svg::render(&buf, &self.overlay(frame)))
+    }
 
-        let max_weight = self.max_weight(heatmap);
+    // render the snapshot as an interactive HTML document
+    fn render_html(&self, frame: &Frame) -> String {
+        let wall_clock = self.wall_clock(frame);
+        let times: Vec<String> = wall_clock
+            .iter()
+            .map(|time| self.format_time(time, &wall_clock[0]))
+            .collect();
+
+        let title = std::path::Path::new(&self.output)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("waterfall");
+
+        html::Document {
+            title,
+            frame,
+            times,
+            colors: &self.colors(Palette::Classic),
+            logarithmic: matches!(self.scale, Scale::Logarithmic),
+            unit: self.unit.as_deref(),
+        }
+        .render()
+    }
 
-        let colors = match self.palette {
-            Palette::Classic => CLASSIC,
-            Palette::Ironbow => IRONBOW,
+    // the annotations for the snapshot, positioned by the configured layout
+    fn overlay(&self, frame: &Frame) -> Overlay {
+        self.annotations(frame, self.max_weight(frame)).place(
+            self.orientation,
+            self.layout,
+            self.font_size,
+        )
+    }
+
+    // the annotations for the snapshot, positioned by column and row
+    fn annotations(&self, frame: &Frame, max_weight: f64) -> Annotations {
+        let value_title = match (&self.value_title, &self.unit) {
+            (Some(title), Some(unit)) => Some(format!("{} ({})", title, unit)),
+            (Some(title), None) => Some(title.clone()),
+            (None, Some(unit)) => Some(unit.clone()),
+            (None, None) => None,
         };
+        let scale_title = format!("count/{}", self.unit.as_deref().unwrap_or("value"));
+        Annotations {
+            columns: frame.columns() as u32,
+            rows: frame.rows() as u32,
+            values: self.value_labels(frame),
+            times: self.time_labels(frame),
+            traces: self.traces(frame),
+            value_title,
+            time_title: self.time_title.clone(),
+            scale_title,
+            scale: self.scale_labels(max_weight),
+            colors: self.colors(Palette::Classic).into_owned(),
+        }
+    }
+
+    // the position and text of each label on the color scale, showing the
+    // count per unit of width which maps to that position
+    fn scale_labels(&self, max_weight: f64) -> Vec<(f64, String)> {
+        let count = 5;
+        (0..count)
+            .map(|i| {
+                let position = i as f64 / (count - 1) as f64;
+                let density = match self.scale {
+                    Scale::Linear => position * max_weight,
+                    Scale::Logarithmic => (position * max_weight).exp2(),
+                };
+                (position, axis::format_float(density))
+            })
+            .collect()
+    }
+
+    // the column of each configured percentile within each window
+    fn traces(&self, frame: &Frame) -> Vec<Trace> {
+        self.percentiles
+            .iter()
+            .map(|(percentile, color)| Trace {
+                label: format!("p{}", percentile),
+                color: *color,
+                points: (0..frame.rows())
+                    .map(|y| frame.percentile(y, *percentile).map(|x| x as u32))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    // the format selected explicitly or by the extension of the output
+    fn output_format(&self) -> Format {
+        if let Some(format) = self.format {
+            return format;
+        }
+        match std::path::Path::new(&self.output)
+            .extension()
+            .and_then(|e| e.to_str())
+        {
+            Some(extension) if extension.eq_ignore_ascii_case("svg") => Format::Svg,
+            Some(extension)
+                if extension.eq_ignore_ascii_case("html")
+                    || extension.eq_ignore_ascii_case("htm") =>
+            {
+                Format::Html
+            }
+            _ => Format::Image,
+        }
+    }
+
+    // render the snapshot into a buffer with one pixel per column and one
+    // pixel per row
+    fn colorize(&self, frame: &Frame) -> Result<RgbImage, WaterfallError> {
+        self.colorize_with(frame, self.max_weight(frame))
+    }
+
+    // colorize the snapshot with weights scaled relative to the provided
+    // maximum weight
+    fn colorize_with(&self, frame: &Frame, max_weight: f64) -> Result<RgbImage, WaterfallError> {
+        let mut weights = weights(frame)?;
+        for y in 0..frame.rows() {
+            for (x, count) in frame.row(y).iter().enumerate() {
+                let scaled_weight = self.scaled_weight(*count, frame.widths[x], max_weight);
+                weights.put_pixel(x as u32, y as u32, Luma([scaled_weight as f32]));
+            }
+        }
+        Ok(self.paint(weights, &self.colors(Palette::Classic)))
+    }
+
+    // the colors of the configured gradient or palette, or of the provided
+    // default
+    fn colors(&self, default: Palette) -> Cow<'static, [ColorRgb]> {
+        match &self.gradient {
+            Some(gradient) => Cow::Owned(gradient.colors()),
+            None => self.palette.unwrap_or(default).colors(),
+        }
+    }
 
+    // map each position (0.0 - 1.0) within the palette to its color, after
+    // applying any smoothing
+    fn paint(&self, weights: ImageBuffer<Luma<f32>, Vec<f32>>, colors: &[ColorRgb]) -> RgbImage {
+        let weights = match self.smooth {
+            Some(sigma) => image::imageops::blur(&weights, sigma),
+            None => weights,
+        };
+
+        let max_index = (colors.len() - 1) as f64;
+        let mut buf = RgbImage::new(weights.width(), weights.height());
+        for (x, y, weight) in weights.enumerate_pixels() {
+            let position = (weight.0[0] as f64).clamp(0.0, 1.0);
+            let color = colors[(position * max_index).round() as usize];
+            buf.put_pixel(x, y, Rgb([color.r, color.g, color.b]));
+        }
+        buf
+    }
+
+    // the column and text for each of the labels across the top
+    fn value_labels(&self, frame: &Frame) -> Vec<(u32, String)> {
         let mut labels = HashMap::new();
         for (k, v) in &self.labels {
-            labels.insert(u64::from(*k), v);
+            labels.insert(u64::from(*k), v.to_string());
+        }
+        if labels.is_empty() {
+            let min = frame.values[0];
+            let max = frame.values[frame.columns() - 1];
+            let spacing = match self.orientation {
+                Orientation::Vertical => 8.0,
+                Orientation::Horizontal => 3.0,
+            };
+            let count = (frame.columns() as f32 / (spacing * self.font_size)) as usize;
+            for tick in axis::ticks(self.axis, min, max, count) {
+                labels.insert(tick, axis::format_value(tick));
+            }
         }
 
         let mut label_keys: Vec<u64> = labels.keys().cloned().collect();
         label_keys.sort_unstable();
 
+        let mut result = Vec::new();
         let mut l = 0;
 
-        if let Some(sigma) = self.smooth {
-            // NOTE: this won't work properly if the palette is > 256 colors
-
-            // build grayscale buffer
-            for (y, slice) in heatmap.into_iter().enumerate() {
-                for (x, b) in slice.histogram().into_iter().enumerate() {
-                    let weight = self.weight(u64::from(b.count()), u64::from(b.width()));
-                    let scaled_weight = weight / max_weight;
-                    let index = (scaled_weight * (colors.len() - 1) as f64).round() as u8;
-                    buf.put_pixel(
-                        x.try_into().unwrap(),
-                        y.try_into().unwrap(),
-                        Rgb([index, index, index]),
-                    );
-                }
-            }
-
-            // apply a blur to smooth
-            buf = image::imageops::blur(&buf, sigma);
+        if label_keys.is_empty() {
+            return result;
+        }
 
-            // colorize the buffer
-            for x in 0..buf.width() {
-                for y in 0..buf.height() {
-                    let index = buf.get_pixel(x, y).0[0];
-                    let color = colors[index as usize];
-                    buf.put_pixel(x, y, Rgb([color.r, color.g, color.b]));
+        for (x, value) in frame.values.iter().enumerate() {
+            if *value >= label_keys[l] {
+                if let Some(label) = labels.get(&label_keys[l]) {
+                    result.push((x as u32, label.to_string()));
                 }
-            }
-        } else {
-            // set the pixels in the buffer
-            for (y, slice) in heatmap.into_iter().enumerate() {
-                for (x, b) in slice.histogram().into_iter().enumerate() {
-                    let weight = self.weight(u64::from(b.count()), u64::from(b.width()));
-                    let scaled_weight = weight / max_weight;
-                    let index = (scaled_weight * (colors.len() - 1) as f64).round() as usize;
-                    let color = colors[index];
-                    buf.put_pixel(
-                        x.try_into().unwrap(),
-                        y.try_into().unwrap(),
-                        Rgb([color.r, color.g, color.b]),
-                    );
+                l += 1;
+                if l >= label_keys.len() {
+                    break;
                 }
             }
         }
 
-        // add the horizontal labels across the top
-        if !label_keys.is_empty() {
-            let slice = heatmap.into_iter().next().unwrap();
-            for (x, bucket) in slice.histogram().into_iter().enumerate() {
-                let value = u64::from(bucket.value());
-                if value >= label_keys[l] {
-                    if let Some(label) = labels.get(&label_keys[l]) {
-                        render_text(label, 25.0, x, 0, &mut buf);
-                        for y in 0..height {
-                            buf.put_pixel(
-                                x.try_into().unwrap(),
-                                y.try_into().unwrap(),
-                                Rgb([255, 255, 255]),
-                            );
-                        }
-                    }
-                    l += 1;
-                    if l >= label_keys.len() {
-                        break;
-                    }
-                }
+        result
+    }
+
+    // the row and text for each of the timestamp labels along the left side
+    fn time_labels(&self, frame: &Frame) -> Vec<(u32, String)> {
+        let mut result = Vec::new();
+
+        let times = self.wall_clock(frame);
+        let interval = self
+            .interval
+            .unwrap_or_else(|| self.auto_interval(frame, &times))
+            .as_nanos()
+            .max(1) as i128;
+
+        // relative labels count intervals from the start of the waterfall,
+        // while others are aligned to round wall-clock times
+        let first = times[0].unix_timestamp_nanos();
+        let origin = match self.time_format {
+            TimeFormat::Relative => first,
+            _ => -(times[0].offset() as i128 * 1_000_000_000),
+        };
+
+        let mut next = (first - origin).div_euclid(interval) + 1;
+        for (y, time) in times.iter().enumerate().take(frame.rows()).skip(1) {
+            let elapsed = time.unix_timestamp_nanos() - origin;
+            if elapsed >= next * interval {
+                result.push((y as u32, self.format_time(time, &times[0])));
+                // rows may cover several intervals once windows are
+                // aggregated
+                next = elapsed.div_euclid(interval) + 1;
             }
         }
 
-        let offset = std::time::Duration::from_nanos((now_instant - begin_instant).as_nanos() as _);
-
-        let begin_utc = now_datetime - offset;
-        let mut begin = begin_instant;
-
-        // add the timestamp labels along the left side
-        for (y, slice) in heatmap.into_iter().enumerate() {
-            let slice_start_utc =
-                begin_utc + Duration::from_nanos((slice.start() - begin_instant).as_nanos() as _);
-
-            if slice.start() - begin >= self.interval {
-                let label = format!("{}", slice_start_utc);
-                render_text(&label, 25.0, 0, y + 2, &mut buf);
-                for x in 0..width {
-                    buf.put_pixel(
-                        x.try_into().unwrap(),
-                        y.try_into().unwrap(),
-                        Rgb([255, 255, 255]),
-                    );
-                }
-                begin += self.interval;
+        result
+    }
+
+    // choose an interval which keeps the time labels from overlapping
+    fn auto_interval(&self, frame: &Frame, times: &[DateTime]) -> Duration {
+        let rows = frame.rows();
+        let span = times[rows].unix_timestamp_nanos() - times[0].unix_timestamp_nanos();
+        let spacing = match self.orientation {
+            Orientation::Vertical => 2.0 * self.font_size,
+            Orientation::Horizontal => {
+                let label = self.format_time(&times[rows], &times[0]);
+                (label.chars().count() as f32 * layout::CHARACTER_WIDTH + 1.0) * self.font_size
+            }
+        };
+        timestamps::interval(Duration::from_nanos(span as u64), rows, spacing as f64)
+    }
+
+    // format the wall-clock time of a row according to the time format
+    fn format_time(&self, time: &DateTime, first: &DateTime) -> String {
+        match &self.time_format {
+            TimeFormat::Rfc3339 => format!("{}", time),
+            TimeFormat::Pattern(pattern) => time.format(pattern),
+            TimeFormat::Relative => {
+                let elapsed = time.unix_timestamp_nanos() - first.unix_timestamp_nanos();
+                timestamps::relative(Duration::from_nanos(elapsed.max(0) as u64))
             }
         }
-        buf.save(&self.output).unwrap();
     }
+
+    // the wall-clock time at the start of each row of the snapshot, followed
+    // by the end of the last row, which is assumed to cover as much time as
+    // the one before it
+    fn wall_clock(&self, frame: &Frame) -> Vec<DateTime> {
+        let begin = frame.starts[0];
+        let begin_utc = self.begin_utc(begin);
+        let rows = frame.rows();
+        let end = if rows > 1 {
+            frame.starts[rows - 1] + (frame.starts[rows - 1] - frame.starts[rows - 2])
+        } else {
+            begin
+        };
+        frame
+            .starts
+            .iter()
+            .chain(std::iter::once(&end))
+            .map(|start| {
+                let utc = begin_utc + Duration::from_nanos((*start - begin).as_nanos() as _);
+                utc.to_offset(self.utc_offset).unwrap_or(utc)
+            })
+            .collect()
+    }
+
+    // the time in UTC at the start of a snapshot, using the anchor if one is
+    // set and otherwise assuming the snapshot ends at the current time
+    fn begin_utc(&self, begin_instant: Instant) -> DateTime {
+        if let Some((anchor_instant, anchor_time)) = self.anchor {
+            return if begin_instant >= anchor_instant {
+                anchor_time + Duration::from_nanos((begin_instant - anchor_instant).as_nanos() as _)
+            } else {
+                anchor_time - Duration::from_nanos((anchor_instant - begin_instant).as_nanos() as _)
+            };
+        }
+
+        let now_datetime = now_utc();
+        let now_instant = Instant::now();
+
+        let offset = std::time::Duration::from_nanos(
+            now_instant
+                .saturating_duration_since(begin_instant)
+                .as_nanos() as _,
+        );
+
+        now_datetime - offset
+    }
+}
+
+// allocate a buffer of weights with one pixel per column and one pixel per
+// row of the snapshot
+fn weights(frame: &Frame) -> Result<ImageBuffer<Luma<f32>, Vec<f32>>, WaterfallError> {
+    let height: u32 = frame
+        .rows()
+        .try_into()
+        .map_err(|_| WaterfallError::TooLarge)?;
+    let width: u32 = frame
+        .columns()
+        .try_into()
+        .map_err(|_| WaterfallError::TooLarge)?;
+    Ok(ImageBuffer::new(width, height))
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -244,36 +685,184 @@ pub(crate) struct ColorRgb {
     pub b: u8,
 }
 
-fn render_text(string: &str, size: f32, x_pos: usize, y_pos: usize, buf: &mut RgbImage) {
-    // load font
-    let font_data = dejavu::sans_mono::regular();
-    let font = Font::try_from_bytes(font_data as &[u8]).unwrap();
-
-    // size and scaling
-    let height: f32 = size;
-    let scale = TypeScale {
-        x: height * 1.0,
-        y: height,
-    };
-
-    let v_metrics = font.v_metrics(scale);
-    let offset = point(0.0, v_metrics.ascent);
-
-    let glyphs: Vec<PositionedGlyph> = font.layout(string, scale, offset).collect();
-
-    for g in glyphs {
-        if let Some(bb) = g.pixel_bounding_box() {
-            g.draw(|x, y, v| {
-                let x = (x as i32 + bb.min.x) as usize;
-                let y = (y as i32 + bb.min.y) as usize;
-                if v > 0.25 {
-                    let x = (x + x_pos).try_into().unwrap();
-                    let y = (y + y_pos).try_into().unwrap();
-                    if x < buf.width() && y < buf.height() {
-                        buf.put_pixel(x, y, Rgb([255, 255, 255]));
-                    }
-                }
-            })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a heatmap covering ten seconds at one second resolution, with the
+    // samples recorded as `(value, count)` in the newest window
+    pub(crate) fn heatmap(samples: &[(u64, u64)]) -> Heatmap<u64, u64> {
+        let mut heatmap = Heatmap::new(1_000, 2, Duration::new(10, 0), Duration::new(1, 0));
+        let now = Instant::now();
+        for (value, count) in samples {
+            heatmap.increment(now, *value, *count);
         }
+        heatmap
+    }
+
+    #[test]
+    fn empty() {
+        let heatmap = Heatmap::<u64, u64>::new(1_000, 2, Duration::new(0, 0), Duration::new(1, 0));
+        let result = WaterfallBuilder::new("empty.png").render(&heatmap);
+        assert!(matches!(result, Err(WaterfallError::Empty)));
+    }
+
+    #[test]
+    fn encode() {
+        let heatmap = heatmap(&[(100, 1)]);
+
+        let png = WaterfallBuilder::new("waterfall.png")
+            .label(100, "100")
+            .percentile(50.0, [255, 0, 0])
+            .encode(&heatmap)
+            .unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+
+        let svg = WaterfallBuilder::new("waterfall.svg")
+            .label(100, "100")
+            .percentile(50.0, [255, 0, 0])
+            .encode(&heatmap)
+            .unwrap();
+        assert!(svg.starts_with(b"<svg"));
+        assert!(String::from_utf8(svg).unwrap().contains("p50"));
+
+        let html = WaterfallBuilder::new("waterfall.html")
+            .encode(&heatmap)
+            .unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>waterfall</title>"));
+        assert!(!html.contains("src="));
+    }
+
+    #[test]
+    fn atomic() {
+        let heatmap = AtomicHeatmap::<u64, AtomicU64>::new(
+            1_000,
+            2,
+            Duration::new(10, 0),
+            Duration::new(1, 0),
+        );
+        heatmap.increment(Instant::now(), 100, 1);
+
+        let image = WaterfallBuilder::new("waterfall.png")
+            .render(&heatmap)
+            .unwrap();
+        assert_eq!(image.width() as usize, heatmap.buckets());
+        assert_eq!(image.height() as usize, heatmap.windows());
+    }
+
+    #[test]
+    fn percentiles() {
+        let heatmap = heatmap(&[(10, 1), (500, 1)]);
+
+        let frame = Frame::new(&heatmap).unwrap();
+        let traces = WaterfallBuilder::<u64>::new("waterfall.png")
+            .percentile(50.0, [255, 0, 0])
+            .percentile(100.0, [0, 0, 255])
+            .traces(&frame);
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[0].label, "p50");
+        assert_eq!(traces[1].label, "p100");
+
+        // only the newest window has samples
+        let newest = frame.rows() - 1;
+        assert!(traces[0].points[..newest].iter().all(|p| p.is_none()));
+        let p50 = traces[0].points[newest].unwrap() as usize;
+        let p100 = traces[1].points[newest].unwrap() as usize;
+        assert_eq!(frame.values[p50], 10);
+        assert!(frame.values[p100] >= 500);
+        assert!(frame.values[p100] - (frame.widths[p100] as u64) < 500);
+    }
+
+    #[test]
+    fn margins() {
+        let heatmap = heatmap(&[(100, 1)]);
+
+        for orientation in &[Orientation::Vertical, Orientation::Horizontal] {
+            let builder = WaterfallBuilder::new("waterfall.svg")
+                .layout(Layout::Margins)
+                .orientation(*orientation)
+                .font_size(12.0)
+                .value_title("latency")
+                .time_title("time")
+                .unit("ns")
+                .percentile(50.0, [255, 0, 0]);
+
+            // the data is surrounded by margins
+            let image = builder.render(&heatmap).unwrap();
+            let (columns, rows) = match orientation {
+                Orientation::Vertical => (heatmap.buckets(), heatmap.windows()),
+                Orientation::Horizontal => (heatmap.windows(), heatmap.buckets()),
+            };
+            assert!(image.width() as usize > columns);
+            assert!(image.height() as usize > rows);
+
+            let svg = String::from_utf8(builder.encode(&heatmap).unwrap()).unwrap();
+            assert!(svg.contains("latency (ns)"));
+            assert!(svg.contains("count/ns"));
+            assert!(svg.contains(r#"font-size="12""#));
+        }
+    }
+
+    #[test]
+    fn resample() {
+        let heatmap = heatmap(&[(10, 1), (500, 3)]);
+
+        for axis in &[Axis::Buckets, Axis::Linear, Axis::Logarithmic] {
+            let builder = WaterfallBuilder::<u64>::new("waterfall.png")
+                .axis(*axis)
+                .width(333);
+
+            // counts are preserved by resampling the value axis
+            let frame = builder.snapshot(&heatmap).unwrap();
+            let total: f64 = frame.counts.iter().sum();
+            assert!((total - 4.0).abs() < 1e-9);
+
+            let image = builder.height(4).render(&heatmap).unwrap();
+            assert_eq!(image.dimensions(), (333, 4));
+        }
+
+        // the linear axis gives each column an equal share of the values
+        let frame = WaterfallBuilder::<u64>::new("waterfall.png")
+            .axis(Axis::Linear)
+            .width(100)
+            .snapshot(&heatmap)
+            .unwrap();
+        assert_eq!(frame.values[0], 10);
+        assert!(frame
+            .widths
+            .iter()
+            .all(|w| (w - frame.widths[0]).abs() < 1e-9));
+        let p50 = frame.percentile(frame.rows() - 1, 50.0).unwrap();
+        assert!((500..=510).contains(&frame.values[p50]));
+    }
+
+    #[test]
+    fn time_labels() {
+        let heatmap = heatmap(&[(100, 1)]);
+        let anchor = DateTime::from(
+            std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_600_000_000_500),
+        );
+
+        let builder = WaterfallBuilder::<u64>::new("waterfall.png")
+            .interval(Duration::from_secs(3))
+            .time_format(TimeFormat::Relative);
+        let frame = builder.snapshot(&heatmap).unwrap();
+        let labels: Vec<String> = builder
+            .time_labels(&frame)
+            .into_iter()
+            .map(|(_, label)| label)
+            .collect();
+        assert_eq!(labels, vec!["T+3s", "T+6s", "T+9s"]);
+
+        // absolute labels are aligned to round times at the offset
+        let builder = WaterfallBuilder::<u64>::new("waterfall.png")
+            .interval(Duration::from_secs(3))
+            .time_format(TimeFormat::Pattern("%H:%M:%S%z".to_string()))
+            .utc_offset(3600)
+            .anchor(frame.starts[0], anchor);
+        let labels = builder.time_labels(&frame);
+        assert_eq!(labels[0], (2, "13:26:42+0100".to_string()));
     }
 }
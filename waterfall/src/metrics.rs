@@ -0,0 +1,76 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Renders waterfalls for heatmaps registered with `rustcommon_metrics_v2`.
+
+use crate::{WaterfallBuilder, WaterfallError};
+
+use image::RgbImage;
+use rustcommon_metrics_v2::{metrics, Heatmap};
+
+impl WaterfallBuilder<u64> {
+    /// Generate the waterfall for the heatmap metric registered with the
+    /// provided name and write it to the output path.
+    pub fn build_metric(self, name: &str) -> Result<(), WaterfallError> {
+        with_heatmap(name, |heatmap| self.build(heatmap))
+    }
+
+    /// Render the waterfall for the heatmap metric registered with the
+    /// provided name into an in-memory raster image.
+    pub fn render_metric(&self, name: &str) -> Result<RgbImage, WaterfallError> {
+        with_heatmap(name, |heatmap| self.render(heatmap))
+    }
+
+    /// Render the waterfall for the heatmap metric registered with the
+    /// provided name and return the bytes of the encoded output.
+    pub fn encode_metric(&self, name: &str) -> Result<Vec<u8>, WaterfallError> {
+        with_heatmap(name, |heatmap| self.encode(heatmap))
+    }
+}
+
+// look up a heatmap metric by name and call the provided function with it
+fn with_heatmap<T, F>(name: &str, f: F) -> Result<T, WaterfallError>
+where
+    F: FnOnce(&Heatmap) -> Result<T, WaterfallError>,
+{
+    let metrics = metrics();
+    let heatmap = metrics
+        .iter()
+        .filter(|entry| entry.name() == name)
+        .find_map(|entry| entry.as_any().and_then(|any| any.downcast_ref::<Heatmap>()));
+    match heatmap {
+        Some(heatmap) => f(heatmap),
+        None => Err(WaterfallError::UnknownMetric(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustcommon_metrics_v2::{metric, Duration, Instant, Lazy};
+
+    #[metric(name = "waterfall.test.heatmap")]
+    static HEATMAP: Lazy<Heatmap> =
+        Lazy::new(|| Heatmap::new(1_000, 2, Duration::from_secs(10), Duration::from_secs(1)));
+
+    #[test]
+    fn lookup() {
+        let builder = WaterfallBuilder::new("waterfall.png");
+
+        // the lazy metric is not registered as a heatmap until initialized
+        assert!(matches!(
+            builder.encode_metric("waterfall.test.heatmap"),
+            Err(WaterfallError::UnknownMetric(_))
+        ));
+
+        HEATMAP.increment(Instant::now(), 100, 1);
+        let png = builder.encode_metric("waterfall.test.heatmap").unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+
+        assert!(matches!(
+            builder.encode_metric("waterfall.test.missing"),
+            Err(WaterfallError::UnknownMetric(_))
+        ));
+    }
+}
@@ -1,11 +1,205 @@
 mod classic;
 mod ironbow;
+mod perceptual;
 
 pub(crate) use classic::CLASSIC;
 pub(crate) use ironbow::IRONBOW;
 
-#[derive(Copy, Clone)]
+use crate::{ColorRgb, WaterfallError};
+use perceptual::*;
+
+use std::borrow::Cow;
+
+// number of colors in the palettes which are generated by interpolation
+const STEPS: usize = 256;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// The color palette used to map weights to colors
 pub enum Palette {
     Classic,
     Ironbow,
+    /// A perceptually uniform palette from dark blue through green to yellow
+    Viridis,
+    /// A perceptually uniform palette from black through purple to pale yellow
+    Magma,
+    /// A perceptually uniform palette which remains distinguishable with color
+    /// vision deficiency
+    Cividis,
+    /// A linear ramp from black to white
+    Grayscale,
+    /// A diverging palette from blue through white to red, used to show
+    /// signed differences
+    Diverging,
+}
+
+impl Palette {
+    /// Returns the colors of the palette from lowest to highest weight
+    pub(crate) fn colors(&self) -> Cow<'static, [ColorRgb]> {
+        match self {
+            Palette::Classic => Cow::Borrowed(CLASSIC),
+            Palette::Ironbow => Cow::Borrowed(IRONBOW),
+            Palette::Viridis => Cow::Owned(Gradient::even(VIRIDIS).colors()),
+            Palette::Magma => Cow::Owned(Gradient::even(MAGMA).colors()),
+            Palette::Cividis => Cow::Owned(Gradient::even(CIVIDIS).colors()),
+            Palette::Grayscale => Cow::Owned(Gradient::even(GRAYSCALE).colors()),
+            Palette::Diverging => Cow::Owned(Gradient::even(DIVERGING).colors()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A gradient which is linearly interpolated between a set of color stops to
+/// produce a custom palette, see `WaterfallBuilder::gradient()`.
+pub struct Gradient {
+    stops: Vec<(f64, ColorRgb)>,
+    steps: usize,
+}
+
+impl Gradient {
+    /// Create a new gradient from a set of stops. Each stop is a position in
+    /// the range 0.0 to 1.0 and the red, green, and blue components of the
+    /// color at that position. At least two stops are required.
+    pub fn new(stops: &[(f64, [u8; 3])]) -> Result<Self, WaterfallError> {
+        if stops.len() < 2 {
+            return Err(WaterfallError::InvalidPalette);
+        }
+        let mut result = Vec::with_capacity(stops.len());
+        for (position, [r, g, b]) in stops {
+            if !(0.0..=1.0).contains(position) {
+                return Err(WaterfallError::InvalidPalette);
+            }
+            result.push((
+                *position,
+                ColorRgb {
+                    r: *r,
+                    g: *g,
+                    b: *b,
+                },
+            ));
+        }
+        result.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Ok(Self {
+            stops: result,
+            steps: STEPS,
+        })
+    }
+
+    /// Sets the number of distinct colors in the palette generated from the
+    /// gradient. Defaults to 256 and is clamped to a minimum of 2.
+    pub fn steps(mut self, steps: usize) -> Self {
+        self.steps = steps.max(2);
+        self
+    }
+
+    // create a gradient from colors which are evenly spaced
+    fn even(colors: &[[u8; 3]]) -> Self {
+        let last = (colors.len() - 1) as f64;
+        let stops = colors
+            .iter()
+            .enumerate()
+            .map(|(i, [r, g, b])| {
+                (
+                    i as f64 / last,
+                    ColorRgb {
+                        r: *r,
+                        g: *g,
+                        b: *b,
+                    },
+                )
+            })
+            .collect();
+        Self {
+            stops,
+            steps: STEPS,
+        }
+    }
+
+    // generate the palette by interpolating between the stops
+    pub(crate) fn colors(&self) -> Vec<ColorRgb> {
+        let mut colors = Vec::with_capacity(self.steps);
+        for step in 0..self.steps {
+            let position = step as f64 / (self.steps - 1) as f64;
+            colors.push(self.color_at(position));
+        }
+        colors
+    }
+
+    // the interpolated color at a position in the gradient
+    fn color_at(&self, position: f64) -> ColorRgb {
+        let first = self.stops[0];
+        if position <= first.0 {
+            return first.1;
+        }
+        for pair in self.stops.windows(2) {
+            let (start, low) = pair[0];
+            let (stop, high) = pair[1];
+            if position <= stop {
+                let fraction = if stop > start {
+                    (position - start) / (stop - start)
+                } else {
+                    1.0
+                };
+                let mix =
+                    |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * fraction).round() as u8;
+                return ColorRgb {
+                    r: mix(low.r, high.r),
+                    g: mix(low.g, high.g),
+                    b: mix(low.b, high.b),
+                };
+            }
+        }
+        self.stops[self.stops.len() - 1].1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gradient() {
+        assert!(Gradient::new(&[(0.0, [0, 0, 0])]).is_err());
+        assert!(Gradient::new(&[(0.0, [0, 0, 0]), (1.5, [0, 0, 0])]).is_err());
+
+        let gradient = Gradient::new(&[(1.0, [255, 255, 255]), (0.0, [0, 0, 0])])
+            .unwrap()
+            .steps(1024);
+        let colors = gradient.colors();
+        assert_eq!(colors.len(), 1024);
+        assert_eq!(colors[0], ColorRgb { r: 0, g: 0, b: 0 });
+        assert_eq!(
+            colors[1023],
+            ColorRgb {
+                r: 255,
+                g: 255,
+                b: 255
+            }
+        );
+        assert_eq!(colors[512].r, 128);
+    }
+
+    #[test]
+    fn perceptual() {
+        for palette in &[Palette::Viridis, Palette::Magma, Palette::Cividis] {
+            let colors = palette.colors();
+            assert_eq!(colors.len(), STEPS);
+        }
+        let viridis = Palette::Viridis.colors();
+        assert_eq!(
+            viridis[0],
+            ColorRgb {
+                r: 0x44,
+                g: 0x01,
+                b: 0x54
+            }
+        );
+        assert_eq!(
+            viridis[STEPS - 1],
+            ColorRgb {
+                r: 0xfd,
+                g: 0xe7,
+                b: 0x25
+            }
+        );
+    }
 }
@@ -0,0 +1,59 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Evenly spaced control points for perceptually uniform color maps. The full
+//! palettes are produced by interpolating between these points.
+
+pub(crate) const VIRIDIS: &[[u8; 3]] = &[
+    [0x44, 0x01, 0x54],
+    [0x47, 0x2d, 0x7b],
+    [0x3b, 0x52, 0x8b],
+    [0x2c, 0x72, 0x8e],
+    [0x21, 0x91, 0x8c],
+    [0x28, 0xae, 0x80],
+    [0x5e, 0xc9, 0x62],
+    [0xad, 0xdc, 0x30],
+    [0xfd, 0xe7, 0x25],
+];
+
+pub(crate) const MAGMA: &[[u8; 3]] = &[
+    [0x00, 0x00, 0x04],
+    [0x1d, 0x11, 0x47],
+    [0x51, 0x12, 0x7c],
+    [0x82, 0x26, 0x81],
+    [0xb6, 0x36, 0x79],
+    [0xe6, 0x51, 0x64],
+    [0xfb, 0x88, 0x61],
+    [0xfe, 0xc2, 0x87],
+    [0xfc, 0xfd, 0xbf],
+];
+
+pub(crate) const CIVIDIS: &[[u8; 3]] = &[
+    [0x00, 0x20, 0x4d],
+    [0x00, 0x33, 0x6f],
+    [0x39, 0x48, 0x6b],
+    [0x57, 0x5c, 0x6d],
+    [0x70, 0x71, 0x73],
+    [0x8a, 0x87, 0x79],
+    [0xa6, 0x9d, 0x75],
+    [0xc4, 0xb5, 0x6c],
+    [0xe4, 0xcf, 0x5b],
+    [0xff, 0xea, 0x46],
+];
+
+pub(crate) const GRAYSCALE: &[[u8; 3]] = &[[0x00, 0x00, 0x00], [0xff, 0xff, 0xff]];
+
+pub(crate) const DIVERGING: &[[u8; 3]] = &[
+    [0x05, 0x30, 0x61],
+    [0x21, 0x66, 0xac],
+    [0x43, 0x93, 0xc3],
+    [0x92, 0xc5, 0xde],
+    [0xd1, 0xe5, 0xf0],
+    [0xf7, 0xf7, 0xf7],
+    [0xfd, 0xdb, 0xc7],
+    [0xf4, 0xa5, 0x82],
+    [0xd6, 0x60, 0x4d],
+    [0xb2, 0x18, 0x2b],
+    [0x67, 0x00, 0x1f],
+];
@@ -0,0 +1,129 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Draws labels, gridlines, and other annotations onto a raster waterfall.
+
+use crate::layout::Overlay;
+use crate::{ColorRgb, WaterfallError};
+
+use image::{Rgb, RgbImage};
+use rusttype::{point, Font, PositionedGlyph, Scale as TypeScale};
+
+const WHITE: ColorRgb = ColorRgb {
+    r: 255,
+    g: 255,
+    b: 255,
+};
+
+/// Place the colorized data and draw the annotations around or over it.
+pub(crate) fn draw(data: &RgbImage, overlay: &Overlay) -> Result<RgbImage, WaterfallError> {
+    let font = font()?;
+
+    let mut buf = RgbImage::new(overlay.width, overlay.height);
+    image::imageops::replace(&mut buf, data, overlay.plot.0, overlay.plot.1);
+
+    for line in &overlay.gridlines {
+        for x in line.x0..=line.x1 {
+            for y in line.y0..=line.y1 {
+                put(&mut buf, x as i64, y as i64, WHITE);
+            }
+        }
+    }
+
+    // percentile traces, joining adjacent points
+    for path in &overlay.paths {
+        let mut previous: Option<(u32, u32)> = None;
+        for next in &path.points {
+            if let Some(next) = next {
+                segment(&mut buf, previous.unwrap_or(*next), *next, path.color);
+            }
+            previous = *next;
+        }
+    }
+
+    for rect in &overlay.rects {
+        for x in rect.x..(rect.x + rect.width) {
+            for y in rect.y..(rect.y + rect.height) {
+                put(&mut buf, x as i64, y as i64, rect.color);
+            }
+        }
+    }
+
+    for text in &overlay.text {
+        render_text(
+            &font,
+            &text.text,
+            overlay.font_size,
+            text.x,
+            text.y,
+            WHITE,
+            &mut buf,
+        );
+    }
+
+    Ok(buf)
+}
+
+// set a pixel, ignoring any which fall outside the buffer
+fn put(buf: &mut RgbImage, x: i64, y: i64, color: ColorRgb) {
+    if x >= 0 && y >= 0 && (x as u32) < buf.width() && (y as u32) < buf.height() {
+        buf.put_pixel(x as u32, y as u32, Rgb([color.r, color.g, color.b]));
+    }
+}
+
+// draw a line between two points
+fn segment(buf: &mut RgbImage, from: (u32, u32), to: (u32, u32), color: ColorRgb) {
+    let (x0, y0) = (from.0 as i64, from.1 as i64);
+    let (x1, y1) = (to.0 as i64, to.1 as i64);
+    let steps = (x1 - x0).abs().max((y1 - y0).abs()).max(1);
+    for step in 0..=steps {
+        let x = x0 + (x1 - x0) * step / steps;
+        let y = y0 + (y1 - y0) * step / steps;
+        put(buf, x, y, color);
+    }
+}
+
+// load the font used for labels
+fn font() -> Result<Font<'static>, WaterfallError> {
+    let font_data = dejavu::sans_mono::regular();
+    Font::try_from_bytes(font_data as &[u8]).ok_or(WaterfallError::Font)
+}
+
+// the scale for text of a given size
+fn scale(size: f32) -> TypeScale {
+    TypeScale {
+        x: size * 1.0,
+        y: size,
+    }
+}
+
+fn render_text(
+    font: &Font,
+    string: &str,
+    size: f32,
+    x_pos: u32,
+    y_pos: u32,
+    color: ColorRgb,
+    buf: &mut RgbImage,
+) {
+    // size and scaling
+    let scale = scale(size);
+
+    let v_metrics = font.v_metrics(scale);
+    let offset = point(0.0, v_metrics.ascent);
+
+    let glyphs: Vec<PositionedGlyph> = font.layout(string, scale, offset).collect();
+
+    for g in glyphs {
+        if let Some(bb) = g.pixel_bounding_box() {
+            g.draw(|x, y, v| {
+                let x = x as i64 + bb.min.x as i64 + x_pos as i64;
+                let y = y as i64 + bb.min.y as i64 + y_pos as i64;
+                if v > 0.25 {
+                    put(buf, x, y, color);
+                }
+            })
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A serialized form of a heatmap, which may be written to a file and read
+//! back later to render a waterfall, such as with the `waterfall` tool.
+
+use crate::source::Frame;
+use crate::{BucketCount, WaterfallError, WindowSource};
+
+use rustcommon_time::{DateTime, Duration, Instant};
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// the first line of the serialized form, which identifies it and its version
+const HEADER: &str = "rustcommon-heatmap,1";
+
+/// The counts in each window of a heatmap, along with the wall-clock time
+/// of the first window, which may be written out and read back to render a
+/// waterfall later. It is created from any `WindowSource`, such as a
+/// `Heatmap`, and is itself a `WindowSource`.
+///
+/// The serialized form is text with one record per line. After the header
+/// line `rustcommon-heatmap,1` come `start,<nanoseconds>`, the start of the
+/// first window since the UNIX epoch, then `values,<value>,...` and
+/// `widths,<width>,...` for the buckets, and then one
+/// `window,<nanoseconds>,<bucket>:<count>,...` line for each window, with
+/// the offset of the window from the first window and the index and count
+/// of each bucket which holds samples.
+pub struct SerializedHeatmap {
+    origin: Instant,
+    start: DateTime,
+    values: Vec<u64>,
+    widths: Vec<u64>,
+    windows: Vec<(u64, Vec<u64>)>,
+}
+
+impl SerializedHeatmap {
+    /// Take a snapshot of the counts in each window of the source, which is
+    /// assumed to end at the current time
+    pub fn new<Value, Source>(source: &Source) -> Result<Self, WaterfallError>
+    where
+        Source: WindowSource<Value> + ?Sized,
+    {
+        let frame = Frame::new(source)?;
+        let origin = frame.starts[0];
+        let start = DateTime::from(SystemTime::now())
+            - std::time::Duration::from_nanos(
+                Instant::now().saturating_duration_since(origin).as_nanos() as _,
+            );
+        let windows = (0..frame.rows())
+            .map(|row| {
+                let offset = (frame.starts[row] - origin).as_nanos() as u64;
+                (offset, frame.row(row).iter().map(|c| *c as u64).collect())
+            })
+            .collect();
+        Ok(Self {
+            origin,
+            start,
+            widths: frame.widths.iter().map(|w| *w as u64).collect(),
+            values: frame.values,
+            windows,
+        })
+    }
+
+    /// Read the serialized form of a heatmap
+    pub fn read<R: Read>(input: R) -> Result<Self, WaterfallError> {
+        let mut lines = BufReader::new(input).lines().enumerate();
+        let mut next = || -> Result<Option<(usize, String)>, WaterfallError> {
+            match lines.next() {
+                Some((i, line)) => Ok(Some((i + 1, line?))),
+                None => Ok(None),
+            }
+        };
+        let invalid = |line: usize, text: &str| {
+            WaterfallError::InvalidHeatmap(format!("line {}: {}", line, text))
+        };
+
+        match next()? {
+            Some((_, line)) if line.trim() == HEADER => {}
+            _ => return Err(WaterfallError::InvalidHeatmap("missing header".to_string())),
+        }
+
+        let mut heatmap = Self {
+            origin: Instant::now(),
+            start: DateTime::from(UNIX_EPOCH),
+            values: Vec::new(),
+            widths: Vec::new(),
+            windows: Vec::new(),
+        };
+        while let Some((number, line)) = next()? {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split(',');
+            let numbers = |fields: std::str::Split<char>| -> Option<Vec<u64>> {
+                fields.map(|f| f.trim().parse().ok()).collect()
+            };
+            match fields.next() {
+                Some("values") | Some("widths") if !heatmap.windows.is_empty() => {
+                    return Err(invalid(number, "buckets after the windows"));
+                }
+                Some("start") => {
+                    let nanos: u64 = fields
+                        .next()
+                        .and_then(|f| f.trim().parse().ok())
+                        .ok_or_else(|| invalid(number, line))?;
+                    heatmap.start =
+                        DateTime::from(UNIX_EPOCH + std::time::Duration::from_nanos(nanos));
+                }
+                Some("values") => {
+                    heatmap.values = numbers(fields).ok_or_else(|| invalid(number, line))?
+                }
+                Some("widths") => {
+                    heatmap.widths = numbers(fields).ok_or_else(|| invalid(number, line))?
+                }
+                Some("window") => {
+                    let columns = heatmap.values.len();
+                    if columns == 0 || heatmap.widths.len() != columns {
+                        return Err(invalid(number, "window before the buckets"));
+                    }
+                    let offset = fields
+                        .next()
+                        .and_then(|f| f.trim().parse().ok())
+                        .ok_or_else(|| invalid(number, line))?;
+                    let mut counts = vec![0; columns];
+                    for field in fields {
+                        let (index, count) = field
+                            .split_once(':')
+                            .and_then(|(i, c)| {
+                                Some((i.trim().parse().ok()?, c.trim().parse().ok()?))
+                            })
+                            .filter(|(i, _): &(usize, u64)| *i < columns)
+                            .ok_or_else(|| invalid(number, line))?;
+                        counts[index] = count;
+                    }
+                    heatmap.windows.push((offset, counts));
+                }
+                _ => return Err(invalid(number, line)),
+            }
+        }
+        Ok(heatmap)
+    }
+
+    /// Write the serialized form of the heatmap
+    pub fn write<W: Write>(&self, output: W) -> Result<(), WaterfallError> {
+        let mut output = std::io::BufWriter::new(output);
+        let join = |numbers: &[u64]| {
+            numbers
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        writeln!(output, "{}", HEADER)?;
+        writeln!(output, "start,{}", self.start.unix_timestamp_nanos())?;
+        writeln!(output, "values,{}", join(&self.values))?;
+        writeln!(output, "widths,{}", join(&self.widths))?;
+        for (offset, counts) in &self.windows {
+            write!(output, "window,{}", offset)?;
+            for (index, count) in counts.iter().enumerate().filter(|(_, c)| **c > 0) {
+                write!(output, ",{}:{}", index, count)?;
+            }
+            writeln!(output)?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Returns the instant which the first window starts at, when the heatmap
+    /// is used as a `WindowSource`. This may be passed to
+    /// `WaterfallBuilder::anchor()` along with `start()` so that the time
+    /// labels show when the heatmap was recorded.
+    pub fn origin(&self) -> Instant {
+        self.origin
+    }
+
+    /// Returns the wall-clock time of the start of the first window
+    pub fn start(&self) -> DateTime {
+        self.start
+    }
+}
+
+impl WindowSource<u64> for SerializedHeatmap {
+    fn for_each_window(
+        &self,
+        visit: &mut dyn FnMut(Instant, &mut dyn Iterator<Item = BucketCount>),
+    ) {
+        for (offset, counts) in &self.windows {
+            let mut buckets =
+                self.values
+                    .iter()
+                    .zip(&self.widths)
+                    .zip(counts)
+                    .map(|((value, width), count)| BucketCount {
+                        value: *value,
+                        width: *width,
+                        count: *count,
+                    });
+            visit(self.origin + Duration::from_nanos(*offset), &mut buckets);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::heatmap;
+
+    #[test]
+    fn round_trip() {
+        let heatmap = heatmap(&[(1, 1), (100, 2), (999, 3)]);
+        let serialized = SerializedHeatmap::new(&heatmap).unwrap();
+        let mut bytes = Vec::new();
+        serialized.write(&mut bytes).unwrap();
+        let read = SerializedHeatmap::read(bytes.as_slice()).unwrap();
+        assert_eq!(
+            read.start().unix_timestamp_nanos(),
+            serialized.start().unix_timestamp_nanos()
+        );
+
+        let (expected, actual) = (Frame::new(&heatmap).unwrap(), Frame::new(&read).unwrap());
+        assert_eq!(actual.values, expected.values);
+        assert_eq!(actual.widths, expected.widths);
+        assert_eq!(actual.counts, expected.counts);
+        let offsets = |frame: &Frame| -> Vec<u64> {
+            frame
+                .starts
+                .iter()
+                .map(|start| (*start - frame.starts[0]).as_nanos() as u64)
+                .collect()
+        };
+        assert_eq!(offsets(&actual), offsets(&expected));
+
+        assert!(SerializedHeatmap::read(&b"1,100\n"[..]).is_err());
+        assert!(SerializedHeatmap::read(&b"rustcommon-heatmap,1\nwindow,0,0:1\n"[..]).is_err());
+    }
+}
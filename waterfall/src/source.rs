@@ -0,0 +1,274 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+use crate::{Axis, WaterfallError};
+
+use rustcommon_heatmap::{Atomic, AtomicCounter, AtomicHeatmap, Counter, Heatmap, Indexing};
+use rustcommon_time::Instant;
+
+use core::ops::Sub;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// The count recorded into a single bucket of a window
+pub struct BucketCount {
+    /// The nominal value of the bucket
+    pub value: u64,
+    /// The range of values stored in the bucket
+    pub width: u64,
+    /// The number of samples recorded into the bucket
+    pub count: u64,
+}
+
+/// A source of windowed distributions which may be rendered as a waterfall.
+/// This is implemented for both `Heatmap` and `AtomicHeatmap`.
+pub trait WindowSource<Value> {
+    /// Calls `visit` once for each window, from oldest to newest, with the
+    /// start of the window and an iterator over its buckets.
+    fn for_each_window(
+        &self,
+        visit: &mut dyn FnMut(Instant, &mut dyn Iterator<Item = BucketCount>),
+    );
+}
+
+impl<Value, Count> WindowSource<Value> for Heatmap<Value, Count>
+where
+    Value: Indexing + Sub<Output = Value>,
+    Count: Counter,
+    u64: From<Value> + From<Count>,
+{
+    fn for_each_window(
+        &self,
+        visit: &mut dyn FnMut(Instant, &mut dyn Iterator<Item = BucketCount>),
+    ) {
+        if self.windows() == 0 {
+            return;
+        }
+        for window in self {
+            let mut buckets = window.histogram().into_iter().map(|b| BucketCount {
+                value: u64::from(b.value()),
+                width: u64::from(b.width()),
+                count: u64::from(b.count()),
+            });
+            visit(window.start(), &mut buckets);
+        }
+    }
+}
+
+impl<Value, Count> WindowSource<Value> for AtomicHeatmap<Value, Count>
+where
+    Value: Indexing + Sub<Output = Value>,
+    Count: AtomicCounter + Default,
+    u64: From<Value> + From<<Count as Atomic>::Primitive>,
+    <Count as Atomic>::Primitive: Counter,
+{
+    fn for_each_window(
+        &self,
+        visit: &mut dyn FnMut(Instant, &mut dyn Iterator<Item = BucketCount>),
+    ) {
+        for window in self {
+            let mut buckets = window.histogram().into_iter().map(|b| BucketCount {
+                value: u64::from(b.value()),
+                width: u64::from(b.width()),
+                count: u64::from(b.count()),
+            });
+            visit(window.start(), &mut buckets);
+        }
+    }
+}
+
+/// A snapshot of the counts within a `WindowSource`. Sources may be modified
+/// concurrently, so rendering works from a single pass over the source to keep
+/// each stage of the rendering consistent.
+pub(crate) struct Frame {
+    /// The start of each window
+    pub starts: Vec<Instant>,
+    /// The nominal value of each bucket
+    pub values: Vec<u64>,
+    /// The width of each bucket
+    pub widths: Vec<f64>,
+    /// The count for each bucket, stored row by row. Counts become fractional
+    /// once the frame is resampled.
+    pub counts: Vec<f64>,
+}
+
+impl Frame {
+    /// Take a snapshot of the provided source
+    pub fn new<Value, Source>(source: &Source) -> Result<Self, WaterfallError>
+    where
+        Source: WindowSource<Value> + ?Sized,
+    {
+        let mut frame = Frame {
+            starts: Vec::new(),
+            values: Vec::new(),
+            widths: Vec::new(),
+            counts: Vec::new(),
+        };
+        source.for_each_window(&mut |start, buckets| {
+            let first = frame.starts.is_empty();
+            let row = frame.counts.len();
+            frame.starts.push(start);
+            for bucket in buckets {
+                if first {
+                    frame.values.push(bucket.value);
+                    frame.widths.push(bucket.width as f64);
+                }
+                frame.counts.push(bucket.count as f64);
+            }
+            // keep rows aligned even if a window reports a different number
+            // of buckets
+            frame.counts.resize(row + frame.values.len(), 0.0);
+        });
+        if frame.starts.is_empty() || frame.values.is_empty() {
+            return Err(WaterfallError::Empty);
+        }
+        Ok(frame)
+    }
+
+    /// Returns the number of windows in the frame
+    pub fn rows(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Returns the number of buckets in each window of the frame
+    pub fn columns(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns the counts for a single window
+    pub fn row(&self, row: usize) -> &[f64] {
+        let columns = self.columns();
+        &self.counts[(row * columns)..((row + 1) * columns)]
+    }
+
+    /// Returns the column holding the requested percentile (0.0 - 100.0) of
+    /// a single window, matching the selection made by `Histogram`. Returns
+    /// `None` if the window has no samples.
+    pub fn percentile(&self, row: usize, percentile: f64) -> Option<usize> {
+        let counts = self.row(row);
+        let total: f64 = counts.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let need = percentile.clamp(0.0, 100.0) / 100.0 * total;
+        let mut have = 0.0;
+        for (column, count) in counts.iter().enumerate() {
+            have += count;
+            if have > 0.0 && have >= need {
+                return Some(column);
+            }
+        }
+        Some(counts.len() - 1)
+    }
+
+    /// Resample the buckets onto the requested number of columns with values
+    /// laid out along the provided axis, and aggregate windows into the
+    /// requested number of rows. Counts are spread across columns in
+    /// proportion to the overlap with each bucket, and rows hold the average
+    /// of the windows they cover.
+    pub fn resample(self, axis: Axis, columns: Option<usize>, rows: Option<usize>) -> Self {
+        let frame = match (axis, columns) {
+            (Axis::Buckets, None) => self,
+            (_, columns) => {
+                let columns = columns.unwrap_or_else(|| self.columns());
+                self.resample_columns(axis, columns.max(1))
+            }
+        };
+        match rows {
+            Some(rows) if rows != frame.rows() => frame.aggregate_rows(rows.max(1)),
+            _ => frame,
+        }
+    }
+
+    fn resample_columns(self, axis: Axis, columns: usize) -> Self {
+        // the range of values held by each bucket, and its extent along the
+        // axis
+        let bounds: Vec<(f64, f64)> = (0..self.columns())
+            .map(|x| {
+                let max = self.values[x] as f64 + 1.0;
+                (max - self.widths[x], max)
+            })
+            .collect();
+        let ranges: Vec<(f64, f64)> = bounds
+            .iter()
+            .enumerate()
+            .map(|(x, (min, max))| axis.range(x, *min, *max))
+            .collect();
+
+        let start = ranges[0].0;
+        let step = (ranges[ranges.len() - 1].1 - start) / columns as f64;
+
+        // the fraction of each bucket which falls within each column
+        let mut overlaps: Vec<Vec<(usize, f64)>> = Vec::with_capacity(columns);
+        let mut values = Vec::with_capacity(columns);
+        let mut widths = Vec::with_capacity(columns);
+        let mut first = 0;
+        for column in 0..columns {
+            let low = start + column as f64 * step;
+            let high = low + step;
+            while first < ranges.len() - 1 && ranges[first].1 <= low {
+                first += 1;
+            }
+            let mut overlap = Vec::new();
+            let mut width = 0.0;
+            let mut last = first;
+            for (x, range) in ranges.iter().enumerate().skip(first) {
+                if range.0 >= high {
+                    break;
+                }
+                last = x;
+                let extent = range.1 - range.0;
+                if extent > 0.0 {
+                    let fraction = (high.min(range.1) - low.max(range.0)) / extent;
+                    if fraction > 0.0 {
+                        overlap.push((x, fraction));
+                        width += fraction * self.widths[x];
+                    }
+                }
+            }
+            // the nominal value is the highest value held by the column
+            let (min, max) = bounds[last];
+            let value = axis.value(high, ranges[last], min, max).ceil() - 1.0;
+            values.push(value.max(0.0) as u64);
+            widths.push(width);
+            overlaps.push(overlap);
+        }
+
+        let mut counts = Vec::with_capacity(self.rows() * columns);
+        for y in 0..self.rows() {
+            let row = self.row(y);
+            for overlap in &overlaps {
+                counts.push(overlap.iter().map(|(x, f)| row[*x] * f).sum());
+            }
+        }
+
+        Frame {
+            starts: self.starts,
+            values,
+            widths,
+            counts,
+        }
+    }
+
+    fn aggregate_rows(self, rows: usize) -> Self {
+        let windows = self.rows();
+        let mut starts = Vec::with_capacity(rows);
+        let mut counts = Vec::with_capacity(rows * self.columns());
+        for row in 0..rows {
+            let first = row * windows / rows;
+            let last = ((row + 1) * windows / rows).max(first + 1);
+            starts.push(self.starts[first]);
+            for x in 0..self.columns() {
+                let total: f64 = (first..last).map(|y| self.row(y)[x]).sum();
+                counts.push(total / (last - first) as f64);
+            }
+        }
+
+        Frame {
+            starts,
+            values: self.values,
+            widths: self.widths,
+            counts,
+        }
+    }
+}
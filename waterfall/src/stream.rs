@@ -0,0 +1,304 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Renders waterfalls incrementally, appending windows as they complete.
+
+use crate::source::Frame;
+use crate::{layout, raster};
+use crate::{WaterfallBuilder, WaterfallError, WindowSource};
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame as AnimationFrame, Rgb, RgbImage};
+use rustcommon_heatmap::Duration;
+use rustcommon_time::Instant;
+
+use core::hash::Hash;
+use std::collections::VecDeque;
+use std::path::Path;
+
+/// A waterfall which scrolls as new windows of a heatmap complete. Each call
+/// to `update()` appends the windows which have completed since the previous
+/// call and captures a frame. Rows are colorized once, when they are appended,
+/// against a maximum weight which is held stable across frames so that colors
+/// do not shift as the waterfall scrolls. Smoothing is not applied. Only the
+/// most recent frames are retained, see `max_frames()`.
+pub struct WaterfallStream<Value> {
+    builder: WaterfallBuilder<Value>,
+    rows: usize,
+    delay: Duration,
+    max_weight: Option<f64>,
+    values: Vec<u64>,
+    widths: Vec<f64>,
+    starts: VecDeque<Instant>,
+    counts: VecDeque<Vec<f64>>,
+    pixels: VecDeque<Vec<Rgb<u8>>>,
+    newest: Option<Instant>,
+    max_frames: usize,
+    captured: usize,
+    frames: VecDeque<RgbImage>,
+}
+
+impl<Value> WaterfallBuilder<Value>
+where
+    Value: Eq + Hash + Copy,
+    u64: From<Value>,
+{
+    /// Create a `WaterfallStream` which retains the provided number of rows,
+    /// with the oldest row dropped as each new one is appended beyond that.
+    pub fn stream(mut self, rows: usize) -> WaterfallStream<Value> {
+        self.smooth = None;
+        WaterfallStream {
+            builder: self,
+            rows: rows.max(1),
+            delay: Duration::from_millis(100),
+            max_weight: None,
+            values: Vec::new(),
+            widths: Vec::new(),
+            starts: VecDeque::new(),
+            counts: VecDeque::new(),
+            pixels: VecDeque::new(),
+            newest: None,
+            max_frames: 600,
+            captured: 0,
+            frames: VecDeque::new(),
+        }
+    }
+}
+
+impl<Value> WaterfallStream<Value>
+where
+    Value: Eq + Hash + Copy,
+    u64: From<Value>,
+{
+    /// Fix the weight which maps to the highest color of the palette. By
+    /// default, this is taken from the first windows which hold any samples.
+    pub fn max_weight(mut self, weight: f64) -> Self {
+        self.max_weight = Some(weight);
+        self
+    }
+
+    /// Sets the maximum number of frames which are retained, with the oldest
+    /// frame dropped as each new one is captured beyond that. Defaults to 600,
+    /// which is one minute of animation at the default delay.
+    pub fn max_frames(mut self, frames: usize) -> Self {
+        self.max_frames = frames.max(1);
+        self
+    }
+
+    /// Sets the delay between frames of an animated output
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Append any windows of the source which have completed since the last
+    /// update and capture a frame if any were appended. The newest window of
+    /// the source is still in progress, so it is not appended. Returns the
+    /// number of rows appended.
+    pub fn update<Source>(&mut self, source: &Source) -> Result<usize, WaterfallError>
+    where
+        Source: WindowSource<Value> + ?Sized,
+    {
+        let frame = Frame::new(source)?.resample(self.builder.axis, self.builder.width, None);
+
+        if self.values.is_empty() {
+            self.values = frame.values.clone();
+            self.widths = frame.widths.clone();
+        } else if self.values != frame.values {
+            return Err(WaterfallError::Mismatch);
+        }
+
+        // skip windows which were already appended
+        let complete = frame.rows() - 1;
+        let first = (0..complete)
+            .find(|y| self.newest.map(|n| frame.starts[*y] > n).unwrap_or(true))
+            .unwrap_or(complete);
+        if first == complete {
+            return Ok(0);
+        }
+
+        let columns = frame.columns();
+        let appended = Frame {
+            starts: frame.starts[first..complete].to_vec(),
+            values: frame.values,
+            widths: frame.widths,
+            counts: frame.counts[(first * columns)..(complete * columns)].to_vec(),
+        };
+
+        let max_weight = match self.max_weight {
+            Some(weight) => weight,
+            None => {
+                let weight = self.builder.max_weight(&appended);
+                if weight > 0.0 {
+                    self.max_weight = Some(weight);
+                }
+                weight
+            }
+        };
+        let buf = self.builder.colorize_with(&appended, max_weight)?;
+
+        for y in 0..appended.rows() {
+            self.starts.push_back(appended.starts[y]);
+            self.counts.push_back(appended.row(y).to_vec());
+            self.pixels.push_back(
+                (0..buf.width())
+                    .map(|x| *buf.get_pixel(x, y as u32))
+                    .collect(),
+            );
+            if self.starts.len() > self.rows {
+                self.starts.pop_front();
+                self.counts.pop_front();
+                self.pixels.pop_front();
+            }
+        }
+        self.newest = appended.starts.last().copied();
+
+        let image = self.render()?;
+        self.frames.push_back(image);
+        self.captured += 1;
+        if self.frames.len() > self.max_frames {
+            self.frames.pop_front();
+        }
+
+        Ok(appended.rows())
+    }
+
+    /// Render the rows which are currently retained. Rows which have not been
+    /// filled yet are left black.
+    pub fn render(&self) -> Result<RgbImage, WaterfallError> {
+        if self.starts.is_empty() {
+            return Err(WaterfallError::Empty);
+        }
+
+        let mut buf = RgbImage::new(self.values.len() as u32, self.rows as u32);
+        for (y, row) in self.pixels.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                buf.put_pixel(x as u32, y as u32, *pixel);
+            }
+        }
+
+        let frame = Frame {
+            starts: self.starts.iter().copied().collect(),
+            values: self.values.clone(),
+            widths: self.widths.clone(),
+            counts: self.counts.iter().flatten().copied().collect(),
+        };
+        let mut annotations = self
+            .builder
+            .annotations(&frame, self.max_weight.unwrap_or(0.0));
+        annotations.rows = self.rows as u32;
+        let overlay = annotations.place(
+            self.builder.orientation,
+            self.builder.layout,
+            self.builder.font_size,
+        );
+
+        raster::draw(&layout::orient(buf, self.builder.orientation), &overlay)
+    }
+
+    /// Returns the frames which are retained, oldest first
+    pub fn frames(&self) -> &VecDeque<RgbImage> {
+        &self.frames
+    }
+
+    /// Encode the retained frames as an animated GIF which loops forever.
+    /// Animated PNG output is not supported by the encoder, so a sequence of
+    /// frames may be written with `build()` instead.
+    pub fn encode_gif(&self) -> Result<Vec<u8>, WaterfallError> {
+        if self.frames.is_empty() {
+            return Err(WaterfallError::Empty);
+        }
+        let delay = Delay::from_numer_denom_ms(self.delay.as_millis() as u32, 1);
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            encoder.set_repeat(Repeat::Infinite)?;
+            encoder.encode_frames(self.frames.iter().map(|frame| {
+                let buffer = DynamicImage::ImageRgb8(frame.clone()).into_rgba8();
+                AnimationFrame::from_parts(buffer, 0, 0, delay)
+            }))?;
+        }
+        Ok(bytes)
+    }
+
+    /// Write the retained frames to the output path. A path with a `.gif`
+    /// extension is written as an animated GIF. Otherwise, each frame is
+    /// written as a separate image, with the frame number appended to the
+    /// name. For example, `load.png` is written as `load-000000.png`,
+    /// `load-000001.png`, and so on. Frames are numbered from the first frame
+    /// captured, so the numbers of retained frames do not change as older
+    /// frames are dropped.
+    pub fn build(&self) -> Result<(), WaterfallError> {
+        let path = Path::new(&self.builder.output);
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("png");
+        if extension.eq_ignore_ascii_case("gif") {
+            std::fs::write(path, self.encode_gif()?)?;
+            return Ok(());
+        }
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("frame");
+        let first = self.captured - self.frames.len();
+        for (index, frame) in self.frames.iter().enumerate() {
+            let name = format!("{}-{:06}.{}", stem, first + index, extension);
+            frame.save(path.with_file_name(name))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustcommon_heatmap::Heatmap;
+
+    #[test]
+    fn scrolls() {
+        let start = Instant::now();
+        let resolution = Duration::from_millis(1);
+        let mut heatmap = Heatmap::<u64, u64>::new(1_000, 2, Duration::from_millis(10), resolution);
+        heatmap.increment(start, 100, 1);
+
+        let mut stream = WaterfallBuilder::new("waterfall.gif").width(50).stream(20);
+        let appended = stream.update(&heatmap).unwrap();
+        assert_eq!(appended, heatmap.windows() - 1);
+
+        // nothing new has completed
+        assert_eq!(stream.update(&heatmap).unwrap(), 0);
+        assert_eq!(stream.frames().len(), 1);
+
+        // advance the heatmap by a few windows
+        heatmap.increment(start + Duration::from_millis(5), 200, 5);
+        assert!(stream.update(&heatmap).unwrap() > 0);
+        assert_eq!(stream.frames().len(), 2);
+        let max_weight = stream.max_weight.unwrap();
+
+        // the max weight is held once the first samples are appended
+        heatmap.increment(start + Duration::from_millis(8), 200, 1);
+        assert!(stream.update(&heatmap).unwrap() > 0);
+        assert_eq!(stream.max_weight, Some(max_weight));
+        assert_eq!(stream.frames().len(), 3);
+        for frame in stream.frames() {
+            assert_eq!(frame.dimensions(), (50, 20));
+        }
+
+        let gif = stream.encode_gif().unwrap();
+        assert_eq!(&gif[0..3], b"GIF");
+    }
+
+    #[test]
+    fn max_frames() {
+        let start = Instant::now();
+        let resolution = Duration::from_millis(1);
+        let mut heatmap = Heatmap::<u64, u64>::new(1_000, 2, Duration::from_millis(10), resolution);
+        let mut stream = WaterfallBuilder::new("waterfall.gif")
+            .width(50)
+            .stream(20)
+            .max_frames(2);
+        for ms in 0..4 {
+            heatmap.increment(start + Duration::from_millis(ms * 3), 100, 1);
+            stream.update(&heatmap).unwrap();
+        }
+        assert_eq!(stream.captured, 4);
+        assert_eq!(stream.frames().len(), 2);
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Renders a colorized waterfall as a scalable vector graphic.
+
+use crate::layout::Overlay;
+use crate::ColorRgb;
+
+use image::RgbImage;
+
+use core::fmt::Write;
+
+/// Render the colorized data and its annotations as an SVG document. Each row
+/// of the data is emitted as a series of rects, with horizontally adjacent
+/// pixels of the same color merged into a single rect. Gridlines, percentile
+/// traces, legends, and labels are then drawn as positioned by the overlay.
+pub(crate) fn render(buf: &RgbImage, overlay: &Overlay) -> String {
+    let mut svg = String::new();
+
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = overlay.width,
+        h = overlay.height
+    );
+
+    // background for any margins around the data
+    if overlay.data != (overlay.width, overlay.height) {
+        let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="black"/>"#);
+    }
+
+    // the data, with each row run-length encoded by color
+    let (width, height) = buf.dimensions();
+    let _ = writeln!(
+        svg,
+        r#"<g shape-rendering="crispEdges" transform="translate({},{})">"#,
+        overlay.plot.0, overlay.plot.1
+    );
+    for y in 0..height {
+        let mut x = 0;
+        while x < width {
+            let color = buf.get_pixel(x, y);
+            let mut run = 1;
+            while x + run < width && buf.get_pixel(x + run, y) == color {
+                run += 1;
+            }
+            let _ = writeln!(
+                svg,
+                r##"<rect x="{}" y="{}" width="{}" height="1" fill="#{:02x}{:02x}{:02x}"/>"##,
+                x, y, run, color.0[0], color.0[1], color.0[2]
+            );
+            x += run;
+        }
+    }
+    let _ = writeln!(svg, "</g>");
+
+    // gridlines through the data at each label
+    let _ = writeln!(
+        svg,
+        r#"<g stroke="white" stroke-width="1" shape-rendering="crispEdges">"#
+    );
+    for line in &overlay.gridlines {
+        if line.x0 == line.x1 {
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{x}.5" y1="{}" x2="{x}.5" y2="{}"/>"#,
+                line.y0,
+                line.y1 + 1,
+                x = line.x0
+            );
+        } else {
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{}" y1="{y}.5" x2="{}" y2="{y}.5"/>"#,
+                line.x0,
+                line.x1 + 1,
+                y = line.y0
+            );
+        }
+    }
+    let _ = writeln!(svg, "</g>");
+
+    // percentile traces, split wherever a window has no samples
+    for path in &overlay.paths {
+        let mut points = Vec::new();
+        for point in path.points.iter().chain(std::iter::once(&None)) {
+            if let Some((x, y)) = point {
+                points.push(format!("{}.5,{}.5", x, y));
+            } else if !points.is_empty() {
+                let _ = writeln!(
+                    svg,
+                    r#"<polyline fill="none" stroke="{}" stroke-width="1" points="{}"/>"#,
+                    hex(path.color),
+                    points.join(" ")
+                );
+                points.clear();
+            }
+        }
+    }
+
+    // legend swatches and the color scale
+    let _ = writeln!(svg, r#"<g shape-rendering="crispEdges">"#);
+    for rect in &overlay.rects {
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+            rect.x,
+            rect.y,
+            rect.width,
+            rect.height,
+            hex(rect.color)
+        );
+    }
+    let _ = writeln!(svg, "</g>");
+
+    // the text for each of the labels, titles, and legends
+    let _ = writeln!(
+        svg,
+        r#"<g fill="white" font-family="DejaVu Sans Mono, monospace" font-size="{}" dominant-baseline="hanging">"#,
+        overlay.font_size
+    );
+    for text in &overlay.text {
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}">{}</text>"#,
+            text.x,
+            text.y,
+            escape(&text.text)
+        );
+    }
+    let _ = writeln!(svg, "</g>");
+
+    let _ = writeln!(svg, "</svg>");
+
+    svg
+}
+
+// format a color as a hex triplet
+fn hex(color: ColorRgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+// escape text for inclusion in an XML or HTML document, including within
+// attribute values
+pub(crate) fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::Text;
+    use image::Rgb;
+
+    #[test]
+    fn merges_runs() {
+        let mut buf = RgbImage::new(4, 2);
+        buf.put_pixel(3, 0, Rgb([255, 0, 0]));
+        for x in 0..4 {
+            buf.put_pixel(x, 1, Rgb([0, 0, 255]));
+        }
+
+        let overlay = Overlay {
+            width: 4,
+            height: 2,
+            plot: (0, 0),
+            data: (4, 2),
+            gridlines: Vec::new(),
+            text: vec![Text {
+                x: 1,
+                y: 0,
+                text: "<1>".to_string(),
+            }],
+            rects: Vec::new(),
+            paths: Vec::new(),
+            font_size: 25.0,
+        };
+        let svg = render(&buf, &overlay);
+
+        assert!(svg.contains(r##"<rect x="0" y="0" width="3" height="1" fill="#000000"/>"##));
+        assert!(svg.contains(r##"<rect x="3" y="0" width="1" height="1" fill="#ff0000"/>"##));
+        assert!(svg.contains(r##"<rect x="0" y="1" width="4" height="1" fill="#0000ff"/>"##));
+        assert_eq!(svg.matches("<rect").count(), 3);
+        assert!(svg.contains("&lt;1&gt;"));
+    }
+}
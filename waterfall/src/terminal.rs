@@ -0,0 +1,192 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Renders waterfalls as text for terminals which support ANSI truecolor.
+
+use crate::source::Frame;
+use crate::{WaterfallBuilder, WaterfallError, WindowSource};
+
+use image::{Rgb, RgbImage};
+use rustcommon_heatmap::{Atomic, AtomicCounter, AtomicHeatmap, Counter, Indexing};
+
+use core::fmt::Write as FmtWrite;
+use core::hash::Hash;
+use core::ops::Sub;
+use std::io::Write;
+
+// the width to use when the terminal size is unknown
+const DEFAULT_COLUMNS: usize = 80;
+
+// clears the screen and moves the cursor to the top left corner
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+// resets all colors to the terminal defaults
+const RESET: &str = "\x1b[0m";
+
+impl<Value> WaterfallBuilder<Value>
+where
+    Value: Eq + Hash + Copy,
+    u64: From<Value>,
+{
+    /// Render the waterfall as text for a terminal which supports ANSI
+    /// truecolor. Each character holds two rows of the waterfall, using the
+    /// upper half block with the older row as the foreground and the newer
+    /// row as the background. Unless a width is set, the waterfall fits the
+    /// width of the terminal attached to stdout. Value labels are written on
+    /// the line below the waterfall. Percentile traces are not drawn.
+    pub fn terminal<Source>(&self, source: &Source) -> Result<String, WaterfallError>
+    where
+        Source: WindowSource<Value> + ?Sized,
+    {
+        let columns = self.width.unwrap_or_else(terminal_columns);
+        let frame = Frame::new(source)?.resample(self.axis, Some(columns), self.height);
+        let buf = self.colorize(&frame)?;
+
+        let mut text = String::new();
+
+        for y in (0..buf.height()).step_by(2) {
+            let mut previous = None;
+            for x in 0..buf.width() {
+                let upper = buf.get_pixel(x, y);
+                let lower = if y + 1 < buf.height() {
+                    Some(buf.get_pixel(x, y + 1))
+                } else {
+                    None
+                };
+                if previous != Some((upper, lower)) {
+                    let _ = write!(text, "\x1b[38;2;{}m", rgb(upper));
+                    match lower {
+                        Some(lower) => {
+                            let _ = write!(text, "\x1b[48;2;{}m", rgb(lower));
+                        }
+                        None => text.push_str("\x1b[49m"),
+                    }
+                    previous = Some((upper, lower));
+                }
+                text.push('▀');
+            }
+            text.push_str(RESET);
+            text.push('\n');
+        }
+
+        text.push_str(&self.label_line(&frame, &buf));
+        text.push('\n');
+
+        Ok(text)
+    }
+
+    /// Repeatedly render the waterfall for a live `AtomicHeatmap` to the
+    /// output, clearing the screen before each refresh. The waterfall is
+    /// refreshed once per resolution of the heatmap, either the provided
+    /// number of times or until writing to the output fails.
+    pub fn watch<Count>(
+        &self,
+        heatmap: &AtomicHeatmap<Value, Count>,
+        output: &mut dyn Write,
+        refreshes: Option<usize>,
+    ) -> Result<(), WaterfallError>
+    where
+        Value: Indexing + Sub<Output = Value>,
+        Count: AtomicCounter + Default,
+        u64: From<<Count as Atomic>::Primitive>,
+        <Count as Atomic>::Primitive: Counter,
+    {
+        let interval = std::time::Duration::from_nanos(heatmap.resolution().as_nanos() as u64);
+        let mut refreshed = 0;
+        while refreshes.map(|r| refreshed < r).unwrap_or(true) {
+            if refreshed > 0 {
+                std::thread::sleep(interval);
+            }
+            let text = match self.terminal(heatmap) {
+                Ok(text) => text,
+                // nothing may have been recorded yet
+                Err(WaterfallError::Empty) => String::new(),
+                Err(e) => return Err(e),
+            };
+            output.write_all(CLEAR.as_bytes())?;
+            output.write_all(text.as_bytes())?;
+            output.flush()?;
+            refreshed += 1;
+        }
+        Ok(())
+    }
+
+    // the value labels, each starting at its column, skipping any which would
+    // overlap the previous label
+    fn label_line(&self, frame: &Frame, buf: &RgbImage) -> String {
+        let mut line = String::new();
+        let mut used = 0;
+        for (x, label) in self.value_labels(frame) {
+            let x = x as usize;
+            if x < used || x + label.chars().count() > buf.width() as usize {
+                continue;
+            }
+            line.push_str(&" ".repeat(x - used));
+            line.push_str(&label);
+            used = x + label.chars().count() + 1;
+            line.push(' ');
+        }
+        line.trim_end().to_string()
+    }
+}
+
+// format a color as the parameters of an SGR truecolor sequence
+fn rgb(color: &Rgb<u8>) -> String {
+    format!("{};{};{}", color.0[0], color.0[1], color.0[2])
+}
+
+// the number of columns of the terminal attached to stdout, falling
+// back to the `COLUMNS` environment variable and then to 80 columns
+fn terminal_columns() -> usize {
+    #[cfg(unix)]
+    {
+        // safety: the winsize struct is fully initialized by a successful
+        // ioctl, and only read in that case
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col > 0 {
+            return size.ws_col as usize;
+        }
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|columns| *columns > 0)
+        .unwrap_or(DEFAULT_COLUMNS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustcommon_heatmap::{AtomicU64, Duration, Instant};
+
+    #[test]
+    fn half_blocks() {
+        let heatmap = AtomicHeatmap::<u64, AtomicU64>::new(
+            1_000,
+            2,
+            Duration::new(10, 0),
+            Duration::from_millis(1),
+        );
+        heatmap.increment(Instant::now(), 100, 1);
+
+        let builder = WaterfallBuilder::new("terminal").width(40).height(5);
+        let text = builder.terminal(&heatmap).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        // five rows fit in three lines, followed by the value labels
+        assert_eq!(lines.len(), 4);
+        for line in &lines[..3] {
+            assert_eq!(line.matches('▀').count(), 40);
+            assert!(line.ends_with(RESET));
+        }
+        assert!(lines[2].contains("\x1b[49m"));
+        assert_eq!(lines[3].trim(), "10");
+
+        let mut output = Vec::new();
+        builder.watch(&heatmap, &mut output, Some(2)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches(CLEAR).count(), 2);
+    }
+}
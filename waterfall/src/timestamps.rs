@@ -0,0 +1,141 @@
+// Copyright 2021 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Formatting of the time labels and selection of the interval between them.
+
+use rustcommon_heatmap::Duration;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const NANOS_PER_MIN: u64 = 60 * NANOS_PER_SEC;
+const NANOS_PER_HOUR: u64 = 60 * NANOS_PER_MIN;
+const NANOS_PER_DAY: u64 = 24 * NANOS_PER_HOUR;
+
+// the intervals between time labels which are considered when the interval
+// is chosen automatically
+const INTERVALS: &[u64] = &[
+    NANOS_PER_MILLI,
+    2 * NANOS_PER_MILLI,
+    5 * NANOS_PER_MILLI,
+    10 * NANOS_PER_MILLI,
+    20 * NANOS_PER_MILLI,
+    50 * NANOS_PER_MILLI,
+    100 * NANOS_PER_MILLI,
+    200 * NANOS_PER_MILLI,
+    500 * NANOS_PER_MILLI,
+    NANOS_PER_SEC,
+    2 * NANOS_PER_SEC,
+    5 * NANOS_PER_SEC,
+    10 * NANOS_PER_SEC,
+    15 * NANOS_PER_SEC,
+    30 * NANOS_PER_SEC,
+    NANOS_PER_MIN,
+    2 * NANOS_PER_MIN,
+    5 * NANOS_PER_MIN,
+    10 * NANOS_PER_MIN,
+    15 * NANOS_PER_MIN,
+    30 * NANOS_PER_MIN,
+    NANOS_PER_HOUR,
+    2 * NANOS_PER_HOUR,
+    3 * NANOS_PER_HOUR,
+    6 * NANOS_PER_HOUR,
+    12 * NANOS_PER_HOUR,
+    NANOS_PER_DAY,
+];
+
+#[derive(Clone, Debug, PartialEq)]
+/// Used to select how the time labels are written
+pub enum TimeFormat {
+    /// RFC 3339 with millisecond precision, such as
+    /// `2021-01-01T00:00:00.000Z`
+    Rfc3339,
+    /// A strftime-like pattern, such as `%H:%M:%S`. See
+    /// `rustcommon_time::DateTime::format()` for the supported specifiers.
+    Pattern(String),
+    /// The time elapsed since the start of the waterfall, such as `T+1m30s`
+    Relative,
+}
+
+/// Choose the smallest round interval which keeps labels at least `spacing`
+/// rows apart, given the time covered by all of the rows.
+pub(crate) fn interval(span: Duration, rows: usize, spacing: f64) -> Duration {
+    let minimum = span.as_nanos() as f64 * spacing / rows.max(1) as f64;
+    let nanos = INTERVALS
+        .iter()
+        .copied()
+        .find(|interval| *interval as f64 >= minimum)
+        .unwrap_or_else(|| {
+            let days = (minimum / NANOS_PER_DAY as f64).ceil() as u64;
+            days.max(1) * NANOS_PER_DAY
+        });
+    Duration::from_nanos(nanos)
+}
+
+/// Format the elapsed time as a relative label, using the largest units
+/// which represent it exactly.
+pub(crate) fn relative(elapsed: Duration) -> String {
+    let nanos = elapsed.as_nanos() as u64;
+    if nanos > 0 && nanos < NANOS_PER_SEC {
+        let millis = nanos as f64 / NANOS_PER_MILLI as f64;
+        return format!("T+{}ms", trim(&format!("{:.3}", millis)));
+    }
+
+    let mut label = "T+".to_string();
+    let (hours, minutes) = (nanos / NANOS_PER_HOUR, nanos / NANOS_PER_MIN % 60);
+    let seconds = nanos % NANOS_PER_MIN;
+    if hours > 0 {
+        label.push_str(&format!("{}h", hours));
+    }
+    if minutes > 0 {
+        label.push_str(&format!("{}m", minutes));
+    }
+    if seconds > 0 || nanos == 0 {
+        let seconds = seconds as f64 / NANOS_PER_SEC as f64;
+        label.push_str(&format!("{}s", trim(&format!("{:.3}", seconds))));
+    }
+    label
+}
+
+// remove trailing zeros from a fixed precision number
+fn trim(number: &str) -> &str {
+    number.trim_end_matches('0').trim_end_matches('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_labels() {
+        assert_eq!(relative(Duration::from_secs(0)), "T+0s");
+        assert_eq!(relative(Duration::from_millis(250)), "T+250ms");
+        assert_eq!(relative(Duration::from_secs(30)), "T+30s");
+        assert_eq!(relative(Duration::from_millis(1_500)), "T+1.5s");
+        assert_eq!(relative(Duration::from_secs(90)), "T+1m30s");
+        assert_eq!(relative(Duration::from_secs(7_200)), "T+2h");
+    }
+
+    #[test]
+    fn adaptive_interval() {
+        // two minutes at one row per second, with labels at least 75 rows
+        // apart
+        assert_eq!(
+            interval(Duration::from_secs(120), 120, 75.0),
+            Duration::from_secs(2 * 60)
+        );
+        assert_eq!(
+            interval(Duration::from_secs(120), 120, 20.0),
+            Duration::from_secs(30)
+        );
+        // a day squeezed into a hundred rows
+        assert_eq!(
+            interval(Duration::from_secs(86_400), 100, 30.0),
+            Duration::from_secs(12 * 3600)
+        );
+        assert_eq!(
+            interval(Duration::from_millis(100), 1000, 100.0),
+            Duration::from_millis(10)
+        );
+    }
+}